@@ -119,6 +119,7 @@ impl Debug for FunctionId {
     }
 }
 
+#[derive(Clone)]
 pub enum Bytecode {
     Minlimit(u8, u8, u8, u32),
     Endprogram(u8, u8, u8, u32),
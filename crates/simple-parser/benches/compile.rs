@@ -0,0 +1,56 @@
+//! 内置J文件的完整编译耗时：分词、解析和生成字节码
+//!
+//! cargo bench -p simple-parser --bench compile
+
+use simple_parser::{Parse, Prelude, Result, SourceFile};
+use std::time::{Duration, Instant};
+
+const ROUNDS: u32 = 10;
+
+fn bench(name: &str, mut f: impl FnMut() -> Result<usize>) -> Result<Duration> {
+    let bytecodes = f()?;
+    let start = Instant::now();
+    for _ in 0..ROUNDS {
+        f()?;
+    }
+    let elapsed = start.elapsed() / ROUNDS;
+    println!("{name:<12} {bytecodes} bytecodes {elapsed:?}/iter");
+    Ok(elapsed)
+}
+
+fn main() -> Result<()> {
+    let commonj = include_str!("../tests/common.j");
+    let blizzardj = include_str!("../tests/blizzard.j");
+    let source = format!("{commonj}\n{blizzardj}");
+    println!("common.j + blizzard.j, {} bytes", source.len());
+
+    bench("common.j", || {
+        let mut parse = Parse::from_slice(commonj.as_bytes());
+        parse.file()?;
+        Ok(parse.program().bytecodes().len())
+    })?;
+    let full = bench("full", || {
+        let mut parse = Parse::from_slice(source.as_bytes());
+        parse.file()?;
+        Ok(parse.program().bytecodes().len())
+    })?;
+
+    // 地图脚本基于预编译的common.j和blizzard.j编译
+    let prelude = Prelude::compile([
+        SourceFile::new("common.j", commonj.into()),
+        SourceFile::new("blizzard.j", blizzardj.into()),
+    ])?;
+    let map =
+        "function main takes nothing returns nothing\ncall BJDebugMsg(\"start\")\nendfunction\n";
+    let prelude = bench("prelude", || {
+        let mut unit = prelude.unit();
+        unit.add_source(SourceFile::new("war3map.j", map.into()));
+        unit.compile()?;
+        Ok(unit.program().bytecodes().len())
+    })?;
+    println!(
+        "prelude is {:.2}x faster",
+        full.as_secs_f64() / prelude.as_secs_f64()
+    );
+    Ok(())
+}
@@ -0,0 +1,47 @@
+//! 比较 `Lex::new` 和 `Lex::from_slice` 在内置J文件上的分词速度
+//!
+//! cargo bench -p simple-parser --bench lex
+
+use simple_parser::{FileId, Lex, Result, Source, Token};
+use std::io::Cursor;
+use std::time::{Duration, Instant};
+
+const ROUNDS: u32 = 20;
+
+fn count<S: Source>(mut lex: Lex<S>) -> Result<usize> {
+    let mut tokens = 0;
+    while lex.next()? != Token::Eos {
+        tokens += 1;
+    }
+    Ok(tokens)
+}
+
+fn bench(name: &str, mut f: impl FnMut() -> Result<usize>) -> Result<Duration> {
+    let tokens = f()?;
+    let start = Instant::now();
+    for _ in 0..ROUNDS {
+        f()?;
+    }
+    let elapsed = start.elapsed() / ROUNDS;
+    println!("{name:<12} {tokens} tokens {elapsed:?}/iter");
+    Ok(elapsed)
+}
+
+fn main() -> Result<()> {
+    let source = format!(
+        "{}\n{}",
+        include_str!("../tests/common.j"),
+        include_str!("../tests/blizzard.j")
+    );
+    println!("common.j + blizzard.j, {} bytes", source.len());
+
+    let read = bench("read", || count(Lex::new(Cursor::new(source.as_str()))))?;
+    let slice = bench("slice", || {
+        count(Lex::from_slice(source.as_bytes(), FileId::default()))
+    })?;
+    println!(
+        "slice is {:.2}x faster",
+        read.as_secs_f64() / slice.as_secs_f64()
+    );
+    Ok(())
+}
@@ -0,0 +1,215 @@
+//! 语法树，parser的输出，字节码生成等后续处理都基于语法树
+use crate::LiteralString;
+use crate::Span;
+use std::sync::Arc;
+
+/// 源码中的名字
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ident {
+    pub name: Arc<str>,
+    pub span: Span,
+}
+
+/// file ::= {declaration}
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct File {
+    pub decls: Vec<Decl>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Decl {
+    Type(TypeDecl),
+    Globals(Globals),
+    Native(NativeDecl),
+    Function(FunctionDecl),
+}
+
+impl Decl {
+    pub fn span(&self) -> Span {
+        match self {
+            Decl::Type(decl) => decl.span,
+            Decl::Globals(decl) => decl.span,
+            Decl::Native(decl) => decl.span,
+            Decl::Function(decl) => decl.span,
+        }
+    }
+}
+
+/// type name extends name
+#[derive(Debug, Clone, PartialEq)]
+pub struct TypeDecl {
+    pub name: Ident,
+    pub extends: Ident,
+    pub span: Span,
+}
+
+/// globals {var_declared} endglobals
+#[derive(Debug, Clone, PartialEq)]
+pub struct Globals {
+    pub vars: Vec<VarDecl>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VarKind {
+    Global,
+    Constant,
+    Local,
+}
+
+/// [constant | local] type [array] name [= exp]
+#[derive(Debug, Clone, PartialEq)]
+pub struct VarDecl {
+    pub kind: VarKind,
+    pub ty: Ident,
+    pub array: bool,
+    pub name: Ident,
+    pub init: Option<Expr>,
+    pub span: Span,
+}
+
+/// type name
+#[derive(Debug, Clone, PartialEq)]
+pub struct Param {
+    pub ty: Ident,
+    pub name: Ident,
+}
+
+/// [constant] (function | native) name takes (nothing | param {, param}) returns (nothing | type)
+#[derive(Debug, Clone, PartialEq)]
+pub struct FunctionSig {
+    pub constant: bool,
+    pub name: Ident,
+    pub params: Vec<Param>,
+    /// `None` 表示 `returns nothing`
+    pub returns: Option<Ident>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct NativeDecl {
+    pub sig: FunctionSig,
+    pub span: Span,
+}
+
+/// function_sig {local var_declared} {stat} endfunction
+#[derive(Debug, Clone, PartialEq)]
+pub struct FunctionDecl {
+    pub sig: FunctionSig,
+    pub locals: Vec<VarDecl>,
+    pub body: Vec<Stmt>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stmt {
+    pub kind: StmtKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[allow(clippy::large_enum_variant)]
+pub enum StmtKind {
+    /// set name [\[exp\]] = exp
+    Set {
+        name: Ident,
+        index: Option<Expr>,
+        value: Expr,
+    },
+    /// call name(explist)
+    Call(Call),
+    If(IfStmt),
+    /// loop {stat} endloop
+    Loop(Vec<Stmt>),
+    /// exitwhen exp
+    Exitwhen(Expr),
+    /// return [exp]
+    Return(Option<Expr>),
+    /// debug stat，release构建中不生成字节码
+    Debug(Box<Stmt>),
+}
+
+/// if exp then {stat} {elseif exp then {stat}} [else {stat}] endif
+#[derive(Debug, Clone, PartialEq)]
+pub struct IfStmt {
+    /// `if` 和每个 `elseif` 分支，第一个是 `if`
+    pub branches: Vec<IfBranch>,
+    pub else_body: Option<Vec<Stmt>>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct IfBranch {
+    pub cond: Expr,
+    pub body: Vec<Stmt>,
+}
+
+/// name(explist)
+#[derive(Debug, Clone, PartialEq)]
+pub struct Call {
+    pub name: Ident,
+    pub args: Vec<Expr>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expr {
+    pub kind: ExprKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExprKind {
+    Integer(u32),
+    /// 单引号包裹的id，如 `'hfoo'`
+    RawCode {
+        value: u32,
+        text: Vec<u8>,
+    },
+    Real(f64),
+    String(LiteralString),
+    Bool(bool),
+    Null,
+    Var(Ident),
+    /// name\[exp\]
+    Index {
+        name: Ident,
+        index: Box<Expr>,
+    },
+    Call(Call),
+    /// function name
+    Code(Ident),
+    Paren(Box<Expr>),
+    Unary {
+        op: UnaryOp,
+        expr: Box<Expr>,
+    },
+    Binary {
+        op: BinaryOp,
+        left: Box<Expr>,
+        right: Box<Expr>,
+    },
+    /// 无法解析的表达式，只在错误恢复时出现
+    Error,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnaryOp {
+    Neg,
+    Not,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Equal,
+    NotEq,
+    LesEq,
+    GreEq,
+    Less,
+    Greater,
+    And,
+    Or,
+}
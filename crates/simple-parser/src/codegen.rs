@@ -0,0 +1,1681 @@
+use crate::ast::{
+    BinaryOp, Call, Decl, Expr, ExprKind, File, FunctionDecl, FunctionSig, Globals, Ident, IfStmt,
+    NativeDecl, Stmt, StmtKind, TypeDecl, UnaryOp, VarDecl, VarKind,
+};
+use crate::Error;
+use crate::ErrorKind;
+use crate::Function;
+use crate::FunctionArg;
+use crate::LiteralString;
+use crate::Program;
+use crate::Result;
+use crate::ScriptType;
+use crate::Span;
+use crate::TypeId;
+use garygo_jass_common::Bytecode;
+use garygo_jass_common::BytecodeValueType;
+use garygo_jass_common::Reg;
+use garygo_jass_common::SymbolId;
+use std::collections::HashMap;
+
+/// 变量的类型和定义位置
+#[derive(Clone)]
+struct Var {
+    script_type: ScriptType,
+    span: Span,
+    constant: bool,
+}
+
+/// 遍历语法树，做语义检查并生成字节码
+#[derive(Clone)]
+pub struct Codegen {
+    program: Program,
+    globals: HashMap<usize, Var>,
+    /// 当前函数的参数和局部变量，在函数外为 `None`
+    locals: Option<HashMap<usize, Var>>,
+    string_index_map: HashMap<Vec<u8>, u32>,
+    loop_label_num: Vec<u32>,
+    label_num: u32,
+    reg: u8,
+    debug: bool,
+    /// 正在生成的函数是否是常量函数，常量函数只能调用常量函数
+    constant: bool,
+    warnings: Vec<Error>,
+}
+
+impl Default for Codegen {
+    fn default() -> Self {
+        Codegen::new()
+    }
+}
+
+impl Codegen {
+    /// 注册了基础类型的codegen
+    pub fn new() -> Codegen {
+        Codegen {
+            program: Program::default(),
+            globals: HashMap::new(),
+            locals: None,
+            string_index_map: HashMap::new(),
+            loop_label_num: vec![],
+            label_num: 0,
+            reg: 0x00,
+            debug: false,
+            constant: false,
+            warnings: vec![],
+        }
+        .with_basetypes()
+    }
+
+    /// 基础类型也在符号表中，`Type` 和 `Extends` 字节码使用符号编号
+    fn with_basetypes(mut self) -> Codegen {
+        let names: Vec<_> = self
+            .program
+            .types
+            .iter()
+            .map(|(_, def)| def.name().to_string())
+            .collect();
+        for name in names {
+            self.symbol_index(&name);
+        }
+        self
+    }
+
+    /// debug构建保留 `debug` 开头的语句，release构建（默认）只做检查不生成字节码
+    pub fn with_debug(mut self, debug: bool) -> Self {
+        self.debug = debug;
+        self
+    }
+
+    /// 目前为止生成的编译结果
+    pub fn program(&self) -> &Program {
+        &self.program
+    }
+
+    pub fn into_program(self) -> Program {
+        self.program
+    }
+
+    /// 不影响生成字节码的警告，如执行不到的语句
+    pub fn warnings(&self) -> &[Error] {
+        &self.warnings
+    }
+
+    pub fn take_warnings(&mut self) -> Vec<Error> {
+        std::mem::take(&mut self.warnings)
+    }
+
+    fn symbol_index(&mut self, symbol: &str) -> usize {
+        self.program.symbol_table.intern(symbol)
+    }
+
+    fn script_type(&self, id: TypeId) -> ScriptType {
+        ScriptType {
+            id,
+            base: self.program.types.get(id).base(),
+            array: false,
+        }
+    }
+
+    fn type_name(&self, script_type: &ScriptType) -> &str {
+        self.program.types.name(script_type.id)
+    }
+
+    fn find_type(&self, name: &str, span: Span) -> Result<ScriptType> {
+        match self.program.types.lookup(name) {
+            Some(id) => Ok(self.script_type(id)),
+            None => err(
+                ErrorKind::UnknownType,
+                span,
+                format!("not found type: {name}"),
+            ),
+        }
+    }
+
+    /// 在函数内定义参数、局部变量，在函数外定义全局变量。
+    /// 同一作用域内不能重名，局部变量和参数可以遮蔽同名的全局变量
+    fn declare_var(
+        &mut self,
+        name: &Ident,
+        script_type: ScriptType,
+        constant: bool,
+    ) -> Result<SymbolId> {
+        let idx = self.symbol_index(&name.name);
+        let scope = match &mut self.locals {
+            Some(locals) => locals,
+            None => &mut self.globals,
+        };
+        if let Some(first) = scope.get(&idx) {
+            let message = format!("duplicate definition variable: {}", name.name);
+            return Err(
+                Error::spanned(ErrorKind::DuplicateDefinition, name.span, message)
+                    .with_label(first.span, "first defined here"),
+            );
+        }
+        let var = Var {
+            script_type,
+            span: name.span,
+            constant,
+        };
+        scope.insert(idx, var);
+        Ok(SymbolId(idx as u32))
+    }
+
+    fn get_var_type(&self, symbol: SymbolId, span: Span) -> Result<&ScriptType> {
+        Ok(&self.get_var(symbol, span)?.script_type)
+    }
+
+    /// 先查当前函数的参数和局部变量，再查全局变量
+    fn get_var(&self, symbol: SymbolId, span: Span) -> Result<&Var> {
+        let idx = u32::from(symbol) as usize;
+        let var = self
+            .locals
+            .as_ref()
+            .and_then(|locals| locals.get(&idx))
+            .or_else(|| self.globals.get(&idx));
+        match var {
+            Some(var) => Ok(var),
+            None => err(
+                ErrorKind::UnknownSymbol,
+                span,
+                format!(
+                    "undeclared variable: {}",
+                    self.program.symbol_table.names[idx]
+                ),
+            ),
+        }
+    }
+
+    fn next_reg(&mut self) -> u8 {
+        if self.reg == u8::MAX {
+            self.reg = 1
+        } else {
+            self.reg += 1;
+        }
+        self.reg
+    }
+
+    fn add_literal_string(&mut self, literal: &LiteralString) -> u32 {
+        if let Some(x) = self.string_index_map.get(&literal.bytes) {
+            return *x;
+        }
+        let idx = self.program.strings.len() as u32;
+        self.program.strings.push(literal.clone());
+        self.string_index_map.insert(literal.bytes.clone(), idx);
+        idx
+    }
+
+    fn next_label(&mut self) -> u32 {
+        self.label_num += 1;
+        self.label_num
+    }
+
+    /// 进入循环，返回循环结束位置的label，`exitwhen` 跳转到这里
+    fn push_loop(&mut self) -> u32 {
+        let label = self.next_label();
+        self.loop_label_num.push(label);
+        label
+    }
+
+    fn current_loop(&mut self, span: Span) -> Result<u32> {
+        let label = match self.loop_label_num.last() {
+            Some(label) => *label,
+            None => {
+                return err(
+                    ErrorKind::InvalidStatement,
+                    span,
+                    "exitwhen is outside loop statement",
+                )
+            }
+        };
+
+        Ok(label)
+    }
+
+    fn pop_loop(&mut self) -> u32 {
+        self.loop_label_num.pop().expect("is not way!")
+    }
+}
+
+fn err<T>(kind: ErrorKind, span: Span, message: impl Into<String>) -> Result<T> {
+    Err(Error::spanned(kind, span, message))
+}
+
+/// 语句块执行完后能否继续执行后面的语句，对永远执行不到的语句给出警告。
+/// `debug` 语句在release构建中会被删除，所以 `debug return` 不算返回
+fn falls_through(body: &[Stmt], warnings: &mut Vec<Error>) -> bool {
+    let mut reachable = true;
+    for stmt in body {
+        if !reachable {
+            // 每个语句块只对第一条执行不到的语句警告
+            let warning = Error::spanned(
+                ErrorKind::UnreachableCode,
+                stmt.span,
+                "unreachable statement",
+            );
+            warnings.push(warning);
+            return false;
+        }
+        reachable = match &stmt.kind {
+            StmtKind::Return(_) => false,
+            StmtKind::If(stmt) => {
+                let mut reachable = stmt.else_body.is_none();
+                for branch in &stmt.branches {
+                    reachable = falls_through(&branch.body, warnings) || reachable;
+                }
+                if let Some(body) = &stmt.else_body {
+                    reachable = falls_through(body, warnings) || reachable;
+                }
+                reachable
+            }
+            // 没有 `exitwhen` 的循环永远不会结束
+            StmtKind::Loop(body) => {
+                falls_through(body, warnings);
+                has_exitwhen(body)
+            }
+            _ => true,
+        };
+    }
+    reachable
+}
+
+/// 循环体中有没有跳出这一层循环的 `exitwhen`，内层循环的不算
+fn has_exitwhen(body: &[Stmt]) -> bool {
+    body.iter().any(|stmt| match &stmt.kind {
+        StmtKind::Exitwhen(_) => true,
+        StmtKind::If(stmt) => {
+            stmt.branches
+                .iter()
+                .any(|branch| has_exitwhen(&branch.body))
+                || stmt.else_body.as_deref().is_some_and(has_exitwhen)
+        }
+        _ => false,
+    })
+}
+
+#[derive(Clone)]
+struct Exp {
+    exp_type: ScriptType,
+    pos: u8,
+    span: Span,
+}
+
+impl BinaryOp {
+    fn bytecode(&self) -> fn(Reg, Reg, Reg) -> Bytecode {
+        match self {
+            BinaryOp::And => Bytecode::And,
+            BinaryOp::Or => Bytecode::Or,
+            BinaryOp::Equal => Bytecode::Equal,
+            BinaryOp::NotEq => Bytecode::Notequal,
+            BinaryOp::LesEq => Bytecode::Lesserequal,
+            BinaryOp::GreEq => Bytecode::Greaterequal,
+            BinaryOp::Less => Bytecode::Lesser,
+            BinaryOp::Greater => Bytecode::Greater,
+            BinaryOp::Add => Bytecode::Add,
+            BinaryOp::Sub => Bytecode::Sub,
+            BinaryOp::Mul => Bytecode::Mul,
+            BinaryOp::Div => Bytecode::Div,
+        }
+    }
+}
+
+impl Codegen {
+    fn can_as_other(&self, one: &ScriptType, other: &ScriptType) -> Option<Option<ScriptType>> {
+        if one.array != other.array {
+            return None;
+        }
+
+        // null只能作为handle、string和code使用
+        if one.base == BytecodeValueType::Null {
+            return matches!(
+                other.base,
+                BytecodeValueType::Handle | BytecodeValueType::String | BytecodeValueType::Code
+            )
+            .then_some(None);
+        }
+
+        if one == other {
+            return Some(None);
+        }
+
+        if one.id == TypeId::INTEGER && other.id == TypeId::REAL {
+            return Some(Some(*other));
+        }
+
+        // 子类型可以作为父类型使用
+        self.program
+            .types
+            .is_subtype(one.id, other.id)
+            .then_some(None)
+    }
+
+    fn cast_to(&mut self, exp: Exp, target: ScriptType) -> Result<Exp> {
+        if exp.exp_type.id == TypeId::INTEGER && target.id == TypeId::REAL {
+            self.program
+                .bytecodes
+                .push(Bytecode::IntToReal(exp.pos.into()));
+            return Ok(Exp {
+                exp_type: target,
+                ..exp
+            });
+        }
+        err(
+            ErrorKind::TypeMismatch,
+            exp.span,
+            "not match cast to method",
+        )
+    }
+
+    /// 把表达式转换成目标类型，允许相同类型、子类型到父类型、`null` 到引用类型和integer到real
+    fn coerce(&mut self, exp: Exp, target: &ScriptType) -> Result<Exp> {
+        match self.can_as_other(&exp.exp_type, target) {
+            Some(None) => Ok(exp),
+            Some(Some(target)) => self.cast_to(exp, target),
+            None => err(
+                ErrorKind::TypeMismatch,
+                exp.span,
+                format!(
+                    "Type error: {} cannot as {}",
+                    self.type_name(&exp.exp_type),
+                    self.type_name(target)
+                ),
+            ),
+        }
+    }
+
+    /// 检查表达式是否是指定的基础类型，如数组下标必须是integer
+    fn expect_base(&self, exp: &Exp, bases: &[BytecodeValueType], what: &str) -> Result<()> {
+        if exp.exp_type.array || !bases.contains(&exp.exp_type.base) {
+            return err(
+                ErrorKind::TypeMismatch,
+                exp.span,
+                format!("{what}, but {}", self.type_name(&exp.exp_type)),
+            );
+        }
+        Ok(())
+    }
+
+    fn do_binop(&mut self, binop: BinaryOp, left: Exp, right: Exp) -> Result<Exp> {
+        let reg = self.next_reg();
+        let op = binop.bytecode();
+        self.program
+            .bytecodes
+            .push(op(reg.into(), left.pos.into(), right.pos.into()));
+
+        let exp_type = match binop {
+            // integer和real运算的结果是real
+            BinaryOp::Add | BinaryOp::Sub | BinaryOp::Mul | BinaryOp::Div => {
+                if right.exp_type.base == BytecodeValueType::Real {
+                    right.exp_type
+                } else {
+                    left.exp_type
+                }
+            }
+            _ => self.script_type(TypeId::BOOLEAN),
+        };
+        Ok(Exp {
+            exp_type,
+            pos: reg,
+            span: left.span.to(right.span),
+        })
+    }
+
+    fn binop_num(&mut self, binop: BinaryOp, left: Exp, right: Exp) -> Result<Exp> {
+        let (left_type, right_type) = (left.exp_type.id, right.exp_type.id);
+        let numeric = |id| id == TypeId::INTEGER || id == TypeId::REAL;
+
+        if left_type == TypeId::STRING
+            && right_type == TypeId::STRING
+            && matches!(binop, BinaryOp::Add)
+        {
+            return self.do_binop(binop, left, right);
+        }
+
+        if numeric(left_type) && numeric(right_type) {
+            if left_type == right_type {
+                // is ok
+            } else if left_type == TypeId::INTEGER {
+                self.program
+                    .bytecodes
+                    .push(Bytecode::IntToReal(left.pos.into()));
+            } else {
+                assert_eq!(right_type, TypeId::INTEGER);
+                self.program
+                    .bytecodes
+                    .push(Bytecode::IntToReal(right.pos.into()));
+            }
+
+            return self.do_binop(binop, left, right);
+        }
+
+        err(
+            ErrorKind::TypeMismatch,
+            left.span.to(right.span),
+            format!(
+                "invail binop: {} {binop:?} {}",
+                self.type_name(&left.exp_type),
+                self.type_name(&right.exp_type)
+            ),
+        )
+    }
+
+    fn binop(&mut self, binop: BinaryOp, left: Exp, right: Exp) -> Result<Exp> {
+        if matches!(
+            binop,
+            BinaryOp::Add
+                | BinaryOp::Sub
+                | BinaryOp::Mul
+                | BinaryOp::Div
+                | BinaryOp::LesEq
+                | BinaryOp::GreEq
+                | BinaryOp::Less
+                | BinaryOp::Greater
+        ) {
+            return self.binop_num(binop, left, right);
+        }
+
+        if matches!(binop, BinaryOp::Equal | BinaryOp::NotEq) {
+            for (one, other) in [(&left, &right), (&right, &left)] {
+                if one.exp_type.base == BytecodeValueType::Null
+                    && other.exp_type.base != BytecodeValueType::Null
+                    && self.can_as_other(&one.exp_type, &other.exp_type).is_none()
+                {
+                    return err(
+                        ErrorKind::TypeMismatch,
+                        left.span.to(right.span),
+                        format!(
+                            "Type error null cannot compare to {}",
+                            self.type_name(&other.exp_type)
+                        ),
+                    );
+                }
+            }
+            if left.exp_type.base != right.exp_type.base
+                && left.exp_type.base != BytecodeValueType::Null
+                && right.exp_type.base != BytecodeValueType::Null
+            {
+                let (left_type, right_type) = (left.exp_type.id, right.exp_type.id);
+                let numeric = |id| id == TypeId::INTEGER || id == TypeId::REAL;
+
+                if numeric(left_type) && numeric(right_type) {
+                    if left_type == right_type {
+                        // is ok
+                    } else if left_type == TypeId::INTEGER {
+                        self.program
+                            .bytecodes
+                            .push(Bytecode::IntToReal(left.pos.into()));
+                    } else {
+                        assert_eq!(right_type, TypeId::INTEGER);
+                        self.program
+                            .bytecodes
+                            .push(Bytecode::IntToReal(right.pos.into()));
+                    }
+                } else {
+                    return err(
+                        ErrorKind::TypeMismatch,
+                        left.span.to(right.span),
+                        format!(
+                            "Type error {} cannot compare to {}",
+                            self.type_name(&left.exp_type),
+                            self.type_name(&right.exp_type)
+                        ),
+                    );
+                }
+            }
+            return self.do_binop(binop, left, right);
+        }
+
+        // and, or
+        if left.exp_type.base != BytecodeValueType::Boolean
+            || right.exp_type.base != BytecodeValueType::Boolean
+        {
+            return err(
+                ErrorKind::TypeMismatch,
+                left.span.to(right.span),
+                format!(
+                    "Type error {} cannot and {}",
+                    self.type_name(&left.exp_type),
+                    self.type_name(&right.exp_type)
+                ),
+            );
+        }
+        self.do_binop(binop, left, right)
+    }
+
+    fn literal(&mut self, base: BytecodeValueType, value: u32, span: Span) -> Exp {
+        let id = match base {
+            BytecodeValueType::Integer => TypeId::INTEGER,
+            BytecodeValueType::Real => TypeId::REAL,
+            BytecodeValueType::String => TypeId::STRING,
+            BytecodeValueType::Boolean => TypeId::BOOLEAN,
+            BytecodeValueType::Null => TypeId::NULL,
+            _ => unreachable!("not a literal type: {base:?}"),
+        };
+        let reg = self.next_reg();
+        self.program
+            .bytecodes
+            .push(Bytecode::SetRegLiteral(reg.into(), base, value));
+        Exp {
+            exp_type: self.script_type(id),
+            pos: reg,
+            span,
+        }
+    }
+
+    fn expression(&mut self, expr: &Expr) -> Result<Exp> {
+        let span = expr.span;
+        let exp = match &expr.kind {
+            ExprKind::String(literal) => {
+                let str_index = self.add_literal_string(literal);
+                self.literal(BytecodeValueType::String, str_index, span)
+            }
+            ExprKind::Null => self.literal(BytecodeValueType::Null, 0, span),
+            ExprKind::Bool(b) => self.literal(BytecodeValueType::Boolean, *b as u32, span),
+            ExprKind::Integer(i) | ExprKind::RawCode { value: i, .. } => {
+                self.literal(BytecodeValueType::Integer, *i, span)
+            }
+            ExprKind::Real(f) => self.literal(BytecodeValueType::Real, (*f as f32).to_bits(), span),
+            ExprKind::Paren(exp) => Exp {
+                span,
+                ..self.expression(exp)?
+            },
+            ExprKind::Call(call) => {
+                let func_ret = self.functioncall(call)?;
+                let ret_type = match func_ret {
+                    Some(ret_type) => ret_type,
+                    None => return err(ErrorKind::TypeMismatch, span, "return nothing is not exp"),
+                };
+                let reg = self.next_reg();
+                self.program
+                    .bytecodes
+                    .push(Bytecode::Move(reg.into(), 0.into()));
+                Exp {
+                    exp_type: ret_type,
+                    pos: reg,
+                    span,
+                }
+            }
+            ExprKind::Var(name) => {
+                let var_symbol = SymbolId(self.symbol_index(&name.name) as u32);
+                let var_type = *self.get_var_type(var_symbol, name.span)?;
+                if var_type.array {
+                    return err(
+                        ErrorKind::TypeMismatch,
+                        span,
+                        format!(
+                            "Assigning an array to a variable is not allowed: {}",
+                            self.type_name(&var_type)
+                        ),
+                    );
+                }
+                let reg = self.next_reg();
+                self.program.bytecodes.push(Bytecode::SetRegVar(
+                    reg.into(),
+                    var_type.base,
+                    var_symbol,
+                ));
+                Exp {
+                    exp_type: var_type,
+                    pos: reg,
+                    span,
+                }
+            }
+            ExprKind::Index { name, index } => {
+                let var_symbol = SymbolId(self.symbol_index(&name.name) as u32);
+                let var_type = *self.get_var_type(var_symbol, name.span)?;
+                if !var_type.array {
+                    return err(
+                        ErrorKind::TypeMismatch,
+                        name.span,
+                        format!(
+                            "cannot index a non-array value: {}",
+                            self.type_name(&var_type)
+                        ),
+                    );
+                }
+                let exp = self.expression(index)?;
+                self.expect_base(
+                    &exp,
+                    &[BytecodeValueType::Integer],
+                    "array index must be integer",
+                )?;
+                let reg = self.next_reg();
+                self.program.bytecodes.push(Bytecode::SetRegVarArray(
+                    reg.into(),
+                    exp.pos.into(),
+                    var_type.base,
+                    var_symbol,
+                ));
+                Exp {
+                    exp_type: ScriptType {
+                        array: false,
+                        ..var_type
+                    },
+                    pos: reg,
+                    span,
+                }
+            }
+            ExprKind::Unary {
+                op: UnaryOp::Neg,
+                expr,
+            } => {
+                // negate
+                let exp = self.expression(expr)?;
+                self.expect_base(
+                    &exp,
+                    &[BytecodeValueType::Integer, BytecodeValueType::Real],
+                    "negate expect integer or real",
+                )?;
+                self.program
+                    .bytecodes
+                    .push(Bytecode::Negate(exp.pos.into()));
+                Exp { span, ..exp }
+            }
+            ExprKind::Unary {
+                op: UnaryOp::Not,
+                expr,
+            } => {
+                // not expression
+                let exp = self.expression(expr)?;
+                if exp.exp_type.base != BytecodeValueType::Boolean {
+                    return err(
+                        ErrorKind::TypeMismatch,
+                        exp.span,
+                        "not expression must be followed by a boolean expression",
+                    );
+                }
+                self.program.bytecodes.push(Bytecode::Not(exp.pos.into()));
+                Exp { span, ..exp }
+            }
+            ExprKind::Code(name) => {
+                let exp_type = self.script_type(TypeId::CODE);
+                let func_idx = self.symbol_index(&name.name);
+                // code只能引用自己定义的函数，native没有字节码
+                let func = match self.program.functions.get(&func_idx) {
+                    Some(func) => func,
+                    None => {
+                        if let Some(native) = self.program.natives.get(&func_idx) {
+                            let message = format!("native {} cannot be used as code", native.name);
+                            return Err(Error::spanned(
+                                ErrorKind::TypeMismatch,
+                                name.span,
+                                message,
+                            )
+                            .with_label(native.span, "native declared here"));
+                        }
+                        self.find_function(func_idx, name.span)?.1
+                    }
+                };
+                if !func.args.is_empty() {
+                    let message = format!("function {} used as code must take nothing", func.name);
+                    return Err(Error::spanned(ErrorKind::TypeMismatch, name.span, message)
+                        .with_label(func.span, "function declared here"));
+                }
+                let reg = self.next_reg();
+                self.program
+                    .bytecodes
+                    .push(Bytecode::SetRegCode(reg.into(), (func_idx as u32).into()));
+                Exp {
+                    exp_type,
+                    pos: reg,
+                    span,
+                }
+            }
+            ExprKind::Binary { op, left, right } => {
+                let left = self.expression(left)?;
+                let right = self.expression(right)?;
+                self.binop(*op, left, right)?
+            }
+            ExprKind::Error => return err(ErrorKind::Syntax, span, "invalid expression"),
+        };
+        Ok(exp)
+    }
+
+    fn find_function(&self, func_idx: usize, span: Span) -> Result<(bool, &Function)> {
+        if let Some(func) = self.program.functions.get(&func_idx) {
+            return Ok((true, func));
+        }
+        if let Some(func) = self.program.natives.get(&func_idx) {
+            return Ok((false, func));
+        }
+        err(
+            ErrorKind::UnknownSymbol,
+            span,
+            format!(
+                "not found function: {}",
+                self.program.symbol_table.names[func_idx]
+            ),
+        )
+    }
+
+    fn functioncall(&mut self, call: &Call) -> Result<Option<ScriptType>> {
+        let func_idx = self.symbol_index(&call.name.name);
+        let (is_func, func) = self.find_function(func_idx, call.name.span)?;
+        let op = if is_func {
+            Bytecode::Calljass
+        } else {
+            Bytecode::Callnative
+        };
+
+        let func = func.clone();
+        if self.constant && !func.constant {
+            let message = format!(
+                "constant function cannot call non-constant function: {}",
+                func.name
+            );
+            return Err(
+                Error::spanned(ErrorKind::NonConstantCall, call.name.span, message)
+                    .with_label(func.span, "function declared here"),
+            );
+        }
+        if call.args.len() != func.args.len() {
+            // 多出的参数指向第一个多余的参数，缺少参数指向整个调用
+            let span = match call.args.get(func.args.len()) {
+                Some(arg) => arg.span,
+                None => call.span,
+            };
+            let message = format!(
+                "function {} takes {} params, but {} given",
+                func.name,
+                func.args.len(),
+                call.args.len()
+            );
+            return Err(Error::spanned(ErrorKind::ArityMismatch, span, message)
+                .with_label(func.span, "function declared here"));
+        }
+        for (arg, param) in call.args.iter().zip(&func.args) {
+            let exp = self.expression(arg)?;
+            let exp = self
+                .coerce(exp, &param.script_type)
+                .map_err(|e| e.with_label(func.span, "function declared here"))?;
+
+            self.program.bytecodes.push(Bytecode::Push(exp.pos.into()));
+        }
+        self.program.bytecodes.push(op(SymbolId(func_idx as u32)));
+        self.program
+            .bytecodes
+            .push(Bytecode::Popn(func.args.len() as u8));
+        Ok(func.ret)
+    }
+
+    fn if_statement(&mut self, stmt: &IfStmt, ret: Option<&ScriptType>) -> Result<()> {
+        let mut jumpend = vec![];
+        let mut jumpelse: Option<usize> = None;
+        for branch in &stmt.branches {
+            if let Some(jumpelse) = jumpelse {
+                self.jump_to_end(&mut jumpend, jumpelse);
+            }
+            let exp = self.expression(&branch.cond)?;
+            let what = "if condition expect a boolean expression";
+            self.expect_base(&exp, &[BytecodeValueType::Boolean], what)?;
+            self.program
+                .bytecodes
+                .push(Bytecode::Jumpiffalse(exp.pos.into(), 0));
+            jumpelse = Some(self.program.bytecodes.len() - 1);
+            self.block(&branch.body, ret)?;
+        }
+
+        if let Some(body) = &stmt.else_body {
+            if let Some(jumpelse) = jumpelse.take() {
+                self.jump_to_end(&mut jumpend, jumpelse);
+            }
+            self.block(body, ret)?;
+        }
+
+        let jump_label = self.next_label();
+        self.program.bytecodes.push(Bytecode::Label(jump_label));
+        for end in jumpend {
+            match self.program.bytecodes.get_mut(end) {
+                Some(Bytecode::Jump(loc)) => *loc = jump_label,
+                _ => panic!("expect Jump"),
+            };
+        }
+        if let Some(jumpelse) = jumpelse {
+            self.patch_jumpiffalse(jumpelse, jump_label);
+        }
+
+        Ok(())
+    }
+
+    /// 上一个分支执行完跳到endif，条件不成立时跳到下一个分支
+    fn jump_to_end(&mut self, jumpend: &mut Vec<usize>, jumpelse: usize) {
+        self.program.bytecodes.push(Bytecode::Jump(0));
+        jumpend.push(self.program.bytecodes.len() - 1);
+        let jump_label = self.next_label();
+        self.program.bytecodes.push(Bytecode::Label(jump_label));
+        self.patch_jumpiffalse(jumpelse, jump_label);
+    }
+
+    fn patch_jumpiffalse(&mut self, jump: usize, label: u32) {
+        match self.program.bytecodes.get_mut(jump) {
+            Some(Bytecode::Jumpiffalse(_, loc)) => *loc = label,
+            _ => panic!("expect Jumpiffalse"),
+        };
+    }
+
+    fn function_sig(&mut self, sig: &FunctionSig, native: bool) -> Result<()> {
+        let func_idx = self.symbol_index(&sig.name.name);
+        if let Ok((_, first)) = self.find_function(func_idx, sig.name.span) {
+            let message = format!("duplicate definition function :{}", sig.name.name);
+            return Err(
+                Error::spanned(ErrorKind::DuplicateDefinition, sig.name.span, message)
+                    .with_label(first.span, "first defined here"),
+            );
+        }
+        if sig.params.len() > 256 {
+            return err(ErrorKind::InvalidDefinition, sig.span, "too much param");
+        }
+
+        let mut func = Function {
+            name: sig.name.name.to_string(),
+            args: vec![],
+            ret: None,
+            span: sig.name.span,
+            constant: sig.constant,
+        };
+        self.program
+            .bytecodes
+            .push(Bytecode::Function(SymbolId(func_idx as u32)));
+
+        if !native {
+            self.locals = Some(HashMap::new());
+        }
+        for (idx, param) in sig.params.iter().enumerate() {
+            let idx = idx as u8;
+            let arg_type = self.find_type(&param.ty.name, param.ty.span)?;
+            if !native {
+                let arg_symbol = self.declare_var(&param.name, arg_type, false)?;
+                self.program
+                    .bytecodes
+                    .push(Bytecode::Funcarg(arg_type.base, idx, arg_symbol));
+            }
+            func.args.push(FunctionArg {
+                name: param.name.name.to_string(),
+                script_type: arg_type,
+                idx,
+            });
+        }
+
+        if let Some(ret) = &sig.returns {
+            func.ret = Some(self.find_type(&ret.name, ret.span)?);
+        }
+
+        if native {
+            self.program.natives.insert(func_idx, func);
+        } else {
+            self.program.functions.insert(func_idx, func);
+        }
+        Ok(())
+    }
+
+    fn set_statement(&mut self, name: &Ident, index: Option<&Expr>, value: &Expr) -> Result<()> {
+        let var_index = self.symbol_index(&name.name);
+        let Var {
+            script_type: var,
+            span,
+            constant,
+        } = self.get_var(SymbolId(var_index as u32), name.span)?.clone();
+        if constant {
+            let message = format!("cannot assign to constant: {}", name.name);
+            return Err(
+                Error::spanned(ErrorKind::AssignToConstant, name.span, message)
+                    .with_label(span, "constant defined here"),
+            );
+        }
+        if var.array != index.is_some() {
+            if var.array {
+                return err(
+                    ErrorKind::TypeMismatch,
+                    name.span,
+                    format!(
+                        "try set array variable into a nonarray variable: {}",
+                        name.name
+                    ),
+                );
+            } else {
+                return err(
+                    ErrorKind::TypeMismatch,
+                    name.span,
+                    format!("try set nonarray variable into a array: {}", name.name),
+                );
+            }
+        }
+
+        let array_index = match index {
+            Some(index) => {
+                let exp = self.expression(index)?;
+                let what = "array index must be integer";
+                self.expect_base(&exp, &[BytecodeValueType::Integer], what)?;
+                Some(exp.pos)
+            }
+            None => None,
+        };
+
+        let exp = self.expression(value)?;
+        let element = ScriptType {
+            array: false,
+            ..var
+        };
+        let exp = self.coerce(exp, &element)?;
+        if let Some(i) = array_index {
+            self.program.bytecodes.push(Bytecode::SetVarArray(
+                i.into(),
+                exp.pos.into(),
+                SymbolId(var_index as u32),
+            ));
+        } else {
+            self.program
+                .bytecodes
+                .push(Bytecode::SetVar(exp.pos.into(), SymbolId(var_index as u32)));
+        }
+
+        Ok(())
+    }
+
+    fn var_declared(&mut self, decl: &VarDecl) -> Result<()> {
+        let op: fn(BytecodeValueType, SymbolId) -> Bytecode = match decl.kind {
+            VarKind::Constant => Bytecode::Constant,
+            VarKind::Local => Bytecode::Local,
+            VarKind::Global => Bytecode::Global,
+        };
+
+        // type
+        let mut script_type = self.find_type(&decl.ty.name, decl.ty.span)?;
+        if decl.array {
+            script_type.array = true;
+            if !matches!(
+                script_type.base,
+                BytecodeValueType::Integer
+                    | BytecodeValueType::Real
+                    | BytecodeValueType::String
+                    | BytecodeValueType::Boolean
+                    | BytecodeValueType::Handle
+            ) {
+                return err(
+                    ErrorKind::InvalidDefinition,
+                    decl.ty.span.to(decl.name.span),
+                    "var array only int, real, string, boolean, handle",
+                );
+            }
+        }
+
+        let constant = decl.kind == VarKind::Constant;
+        if constant && decl.init.is_none() {
+            return err(
+                ErrorKind::InvalidDefinition,
+                decl.name.span,
+                format!("constant must be initialized: {}", decl.name.name),
+            );
+        }
+
+        // var
+        let base = script_type.base;
+        let var_symbol = self.declare_var(&decl.name, script_type, constant)?;
+        self.program.bytecodes.push(op(base, var_symbol));
+
+        let Some(init) = &decl.init else {
+            // 只定义变量不赋值
+            return Ok(());
+        };
+
+        if decl.array {
+            return err(
+                ErrorKind::InvalidDefinition,
+                init.span,
+                "array is not allow init",
+            );
+        }
+
+        let exp = self.expression(init)?;
+        let exp = self.coerce(exp, &script_type)?;
+        self.program
+            .bytecodes
+            .push(Bytecode::SetVar(exp.pos.into(), var_symbol));
+
+        Ok(())
+    }
+
+    fn global_variables(&mut self, globals: &Globals) -> Result<()> {
+        for var in &globals.vars {
+            self.var_declared(var)?;
+        }
+        Ok(())
+    }
+
+    fn type_definition(&mut self, decl: &TypeDecl) -> Result<()> {
+        self.program.types.define(decl)?;
+        let derived = self.symbol_index(&decl.name.name);
+        let base_index = self.symbol_index(&decl.extends.name);
+        self.program.bytecodes.push(Bytecode::Type(derived.into()));
+        self.program
+            .bytecodes
+            .push(Bytecode::Extends(base_index.into()));
+        Ok(())
+    }
+
+    fn native_function(&mut self, decl: &NativeDecl) -> Result<()> {
+        self.function_sig(&decl.sig, true)
+    }
+
+    fn user_defined_function(&mut self, decl: &FunctionDecl) -> Result<()> {
+        let res = self.function_body(decl);
+        // 出错时也要清理函数内的状态，错误恢复模式下后面的声明还会继续检查
+        self.locals = None;
+        self.constant = false;
+        self.loop_label_num.clear();
+        res
+    }
+
+    fn function_body(&mut self, decl: &FunctionDecl) -> Result<()> {
+        self.function_sig(&decl.sig, false)?;
+        self.constant = decl.sig.constant;
+        for local in &decl.locals {
+            self.var_declared(local)?;
+        }
+        let ret = match &decl.sig.returns {
+            Some(ret) => Some(self.find_type(&ret.name, ret.span)?),
+            None => None,
+        };
+        self.block(&decl.body, ret.as_ref())?;
+        if falls_through(&decl.body, &mut self.warnings) && ret.is_some() {
+            return err(
+                ErrorKind::MissingReturn,
+                decl.sig.name.span,
+                format!(
+                    "function {} does not return on every path",
+                    decl.sig.name.name
+                ),
+            );
+        }
+        self.program.bytecodes.push(Bytecode::Return);
+        self.program.bytecodes.push(Bytecode::Endfunction);
+        Ok(())
+    }
+
+    fn block(&mut self, body: &[Stmt], ret: Option<&ScriptType>) -> Result<()> {
+        for stmt in body {
+            self.statement(stmt, ret)?;
+        }
+        Ok(())
+    }
+
+    fn statement(&mut self, stmt: &Stmt, ret: Option<&ScriptType>) -> Result<()> {
+        match &stmt.kind {
+            StmtKind::Set { name, index, value } => {
+                self.set_statement(name, index.as_ref(), value)?;
+            }
+            StmtKind::Call(call) => {
+                self.functioncall(call)?;
+            }
+            StmtKind::Return(value) => {
+                match (value, ret) {
+                    (Some(value), Some(ret)) => {
+                        let exp = self.expression(value)?;
+                        let exp = self.coerce(exp, ret)?;
+                        self.program
+                            .bytecodes
+                            .push(Bytecode::Move(0.into(), exp.pos.into()));
+                    }
+                    (None, None) => {}
+                    (Some(value), None) => {
+                        return err(
+                            ErrorKind::TypeMismatch,
+                            value.span,
+                            "function returns nothing, but return a value",
+                        )
+                    }
+                    (None, Some(_)) => {
+                        return err(ErrorKind::TypeMismatch, stmt.span, "expect return value")
+                    }
+                }
+                self.program.bytecodes.push(Bytecode::Return);
+            }
+            StmtKind::Loop(body) => {
+                let start = self.next_label();
+                self.program.bytecodes.push(Bytecode::Label(start));
+                self.push_loop();
+                self.block(body, ret)?;
+                self.program.bytecodes.push(Bytecode::Jump(start));
+                let end = self.pop_loop();
+                self.program.bytecodes.push(Bytecode::Label(end));
+            }
+            StmtKind::Exitwhen(cond) => {
+                let exp = self.expression(cond)?;
+                let what = "exitwhen expect a boolean expression";
+                self.expect_base(&exp, &[BytecodeValueType::Boolean], what)?;
+                let label = self.current_loop(stmt.span)?;
+                self.program
+                    .bytecodes
+                    .push(Bytecode::Jumpiftrue(exp.pos.into(), label));
+            }
+            StmtKind::If(stmt) => {
+                self.if_statement(stmt, ret)?;
+            }
+            StmtKind::Debug(stmt) => {
+                let start = self.program.bytecodes.len();
+                let strings = self.program.strings.len();
+                let (label_num, reg) = (self.label_num, self.reg);
+                self.statement(stmt, ret)?;
+                if !self.debug {
+                    // release构建丢弃整条语句生成的字节码，以及它加入的字符串和用掉的label、寄存器
+                    self.program.bytecodes.truncate(start);
+                    self.program.strings.truncate(strings);
+                    self.string_index_map
+                        .retain(|_, idx| (*idx as usize) < strings);
+                    self.label_num = label_num;
+                    self.reg = reg;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// 按声明顺序检查并生成整个文件的字节码
+    pub fn file(&mut self, file: &File) -> Result<()> {
+        for decl in &file.decls {
+            self.decl(decl)?;
+        }
+        Ok(())
+    }
+
+    /// 和 `file` 一样，但是声明出错后继续检查后面的声明，返回所有语义错误。
+    /// 出错的声明只生成了一部分字节码，有错误时编译结果不能使用
+    pub fn file_with_recovery(&mut self, file: &File) -> Vec<Error> {
+        let mut errors = vec![];
+        for decl in &file.decls {
+            match decl {
+                // 全局变量逐个检查，出错的变量后面的变量也要定义，否则使用它们时会报未定义
+                Decl::Globals(globals) => errors.extend(
+                    globals
+                        .vars
+                        .iter()
+                        .filter_map(|var| self.var_declared(var).err()),
+                ),
+                decl => errors.extend(self.decl(decl).err()),
+            }
+        }
+        errors
+    }
+
+    fn decl(&mut self, decl: &Decl) -> Result<()> {
+        match decl {
+            Decl::Globals(globals) => self.global_variables(globals),
+            Decl::Type(decl) => self.type_definition(decl),
+            Decl::Native(decl) => self.native_function(decl),
+            Decl::Function(decl) => self.user_defined_function(decl),
+        }
+    }
+}
+
+/// 测试用：解析并编译一段源码
+#[cfg(test)]
+fn compile(input: &[u8]) -> Result<Codegen> {
+    let file = crate::Parse::from_slice(input).parse_file()?;
+    let mut codegen = Codegen::new();
+    codegen.file(&file)?;
+    Ok(codegen)
+}
+
+#[test]
+fn test_rawcode_literal() -> Result<()> {
+    use crate::Parse;
+    use std::io::Cursor;
+
+    let input_str = "globals \n constant integer footman = 'hfoo' \n endglobals";
+    let mut parse = Parse::test_instance(Cursor::new(input_str))?;
+    parse.file()?;
+    assert!(parse.program().bytecodes().iter().any(|bytecode| matches!(
+        bytecode,
+        Bytecode::SetRegLiteral(_, BytecodeValueType::Integer, 0x68666F6F)
+    )));
+
+    Ok(())
+}
+
+#[test]
+fn test_debug_statement() -> Result<()> {
+    use crate::Parse;
+    use std::io::Cursor;
+
+    let input_str = "native Log takes string s returns nothing \n function Main takes nothing returns integer \n local integer i = 0 \n debug set i = 1 \n debug call Log(\"x\") \n debug if i == 1 then \n endif \n debug loop \n exitwhen true \n endloop \n debug return 1 \n return 0 \n endfunction";
+    let mut release = Parse::test_instance(Cursor::new(input_str))?.with_newlines(true);
+    release.file()?;
+    assert!(!release
+        .program()
+        .bytecodes()
+        .iter()
+        .any(|bytecode| matches!(bytecode, Bytecode::Callnative(_) | Bytecode::Jumpiftrue(..))));
+
+    let mut debug = Parse::test_instance(Cursor::new(input_str))?
+        .with_newlines(true)
+        .with_debug(true);
+    debug.file()?;
+    assert!(debug
+        .program()
+        .bytecodes()
+        .iter()
+        .any(|bytecode| matches!(bytecode, Bytecode::Callnative(_))));
+    assert!(debug
+        .program()
+        .bytecodes()
+        .iter()
+        .any(|bytecode| matches!(bytecode, Bytecode::Jumpiftrue(..))));
+    assert!(debug.program().bytecodes().len() > release.program().bytecodes().len());
+    let strings = |parse: &Parse<_>| -> Vec<String> {
+        let strings = parse.program().strings().iter();
+        strings.map(|s| s.text.clone()).collect()
+    };
+    assert_eq!(strings(&release), Vec::<String>::new());
+    assert_eq!(strings(&debug), ["x"]);
+
+    let input_str =
+        "function Main takes nothing returns nothing \n debug exitwhen true \n endfunction";
+    let mut parse = Parse::test_instance(Cursor::new(input_str))?;
+    assert!(parse.file().is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_string_literal_encoding() -> Result<()> {
+    use crate::Parse;
+    use std::io::Cursor;
+
+    let input_str = b"globals \n constant string hello = \"\xC4\xE3\xBA\xC3\" \n endglobals";
+    let mut parse =
+        Parse::test_instance(Cursor::new(input_str))?.with_encoding(crate::SourceEncoding::Gbk);
+    parse.file()?;
+    assert_eq!(
+        parse.codegen().program().strings(),
+        vec![LiteralString {
+            text: "你好".to_string(),
+            bytes: vec![0xC4, 0xE3, 0xBA, 0xC3],
+        }]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_loop_and_return() -> Result<()> {
+    use crate::Parse;
+
+    let input_str = b"function F takes integer x returns integer\n loop\n exitwhen x > 10\n set x = x + 1\n endloop\n return x\nendfunction";
+    let mut parse = Parse::from_slice(input_str);
+    let file = parse.parse_file()?;
+    let mut codegen = Codegen::new();
+    codegen.file(&file)?;
+
+    let labels: Vec<_> = codegen
+        .program
+        .bytecodes
+        .iter()
+        .filter_map(|bytecode| match bytecode {
+            Bytecode::Label(label) => Some(*label),
+            _ => None,
+        })
+        .collect();
+    let [start, end] = labels[..] else {
+        panic!("expect loop start and end label");
+    };
+    assert!(codegen
+        .program
+        .bytecodes
+        .iter()
+        .any(|bytecode| matches!(bytecode, Bytecode::Jumpiftrue(_, label) if *label == end)));
+    assert!(codegen
+        .program
+        .bytecodes
+        .iter()
+        .any(|bytecode| matches!(bytecode, Bytecode::Jump(label) if *label == start)));
+
+    // 返回值放在0号寄存器
+    let ret = codegen
+        .program
+        .bytecodes
+        .iter()
+        .position(|bytecode| matches!(bytecode, Bytecode::Return))
+        .unwrap();
+    let Bytecode::Move(dst, _) = codegen.program.bytecodes[ret - 1] else {
+        panic!("expect move return value");
+    };
+    assert_eq!(u8::from(dst), 0);
+    assert!(matches!(
+        codegen.program.bytecodes.last(),
+        Some(Bytecode::Endfunction)
+    ));
+
+    let input_str = b"function F takes nothing returns nothing\n return 1\nendfunction";
+    let file = Parse::from_slice(input_str).parse_file()?;
+    assert!(Codegen::new().file(&file).is_err());
+    Ok(())
+}
+
+#[test]
+fn test_error_kind() -> Result<()> {
+    use crate::Parse;
+
+    let check = |input: &[u8], kind: ErrorKind| -> Result<Error> {
+        let file = Parse::from_slice(input).parse_file()?;
+        let e = Codegen::new().file(&file).unwrap_err();
+        assert_eq!(e.kind(), kind, "{e}");
+        Ok(e)
+    };
+
+    let input = b"function F takes integer a returns nothing\nendfunction\nfunction F takes nothing returns nothing\nendfunction";
+    let e = check(input, ErrorKind::DuplicateDefinition)?;
+    assert_eq!(e.code(), "E0303");
+    assert_eq!(e.span().unwrap().start_line, 3);
+    assert_eq!(e.labels().len(), 1);
+    assert_eq!(e.labels()[0].span.start_line, 1);
+    assert_eq!(e.labels()[0].message, "first defined here");
+
+    let input = b"function F takes integer a returns nothing\nendfunction\nfunction G takes nothing returns nothing\ncall F(1, 2)\nendfunction";
+    let e = check(input, ErrorKind::ArityMismatch)?;
+    let span = e.span().unwrap();
+    assert_eq!((span.start_line, span.start_col), (4, 11));
+    assert_eq!(e.labels()[0].message, "function declared here");
+
+    let input = b"function G takes nothing returns nothing\ncall F()\nendfunction";
+    check(input, ErrorKind::UnknownSymbol)?;
+    let input = b"globals\nunit u\nendglobals";
+    check(input, ErrorKind::UnknownType)?;
+    let input = b"globals\ninteger i = \"s\" + 1\nendglobals";
+    check(input, ErrorKind::TypeMismatch)?;
+    let input = b"function G takes nothing returns nothing\nexitwhen true\nendfunction";
+    check(input, ErrorKind::InvalidStatement)?;
+    Ok(())
+}
+
+#[test]
+fn test_function_scope() -> Result<()> {
+    // 局部变量遮蔽全局变量，函数结束后全局变量恢复可见
+    let input = b"globals\nstring s\nendglobals
+function A takes integer s returns nothing
+local integer i = s + 1
+endfunction
+function B takes nothing returns nothing
+local integer s = 1
+set s = s + 1
+endfunction
+function C takes nothing returns nothing
+set s = \"s\"
+endfunction";
+    compile(input)?;
+
+    let input = b"function A takes nothing returns nothing
+local integer i
+endfunction
+function B takes nothing returns nothing
+set i = 1
+endfunction";
+    let e = compile(input).err().unwrap();
+    assert_eq!(e.kind(), ErrorKind::UnknownSymbol);
+    assert_eq!(e.message(), "undeclared variable: i");
+    assert_eq!(e.span().unwrap().start_line, 5);
+
+    let input = b"function A takes integer a returns nothing
+local integer i
+local real i
+endfunction";
+    let e = compile(input).err().unwrap();
+    assert_eq!(e.kind(), ErrorKind::DuplicateDefinition);
+    assert_eq!(e.span().unwrap().start_line, 3);
+    assert_eq!(e.labels()[0].span.start_line, 2);
+
+    let input = b"function A takes integer a, real a returns nothing\nendfunction";
+    let e = compile(input).err().unwrap();
+    assert_eq!(e.kind(), ErrorKind::DuplicateDefinition);
+
+    let input = b"function A takes integer a returns nothing\nlocal integer a\nendfunction";
+    let e = compile(input).err().unwrap();
+    assert_eq!(e.kind(), ErrorKind::DuplicateDefinition);
+    Ok(())
+}
+
+#[test]
+fn test_constant() -> Result<()> {
+    let input = b"globals\nconstant integer A = 1\nendglobals
+constant native N takes nothing returns integer
+native M takes nothing returns integer
+constant function F takes nothing returns integer
+return A + N()
+endfunction
+constant function G takes nothing returns integer
+return F()
+endfunction
+function H takes nothing returns integer
+return G() + M()
+endfunction";
+    compile(input)?;
+
+    let e = compile(b"globals\nconstant integer A\nendglobals")
+        .err()
+        .unwrap();
+    assert_eq!(e.kind(), ErrorKind::InvalidDefinition);
+
+    let input = b"globals\nconstant integer A = 1\nendglobals
+function F takes nothing returns nothing
+set A = 2
+endfunction";
+    let e = compile(input).err().unwrap();
+    assert_eq!(e.kind(), ErrorKind::AssignToConstant);
+    assert_eq!(e.span().unwrap().start_line, 5);
+    assert_eq!(e.labels()[0].message, "constant defined here");
+    assert_eq!(e.labels()[0].span.start_line, 2);
+
+    let input = b"native M takes nothing returns integer
+constant function F takes nothing returns integer
+return M()
+endfunction";
+    let e = compile(input).err().unwrap();
+    assert_eq!(e.kind(), ErrorKind::NonConstantCall);
+    assert_eq!(e.code(), "E0503");
+    assert_eq!(e.labels()[0].span.start_line, 1);
+    Ok(())
+}
+
+#[test]
+fn test_type_check() -> Result<()> {
+    let compile_body = |input: &str| -> Result<Codegen> {
+        let input = format!(
+            "type agent extends handle
+type unit extends agent
+type item extends agent
+native N takes nothing returns nothing
+globals
+integer i = 1
+real r = 1
+string s = null
+unit u = null
+agent a = u
+boolean b = true
+integer array ia
+code c = null
+endglobals
+function F takes agent a returns real
+return 1
+endfunction
+function G takes nothing returns nothing
+{input}
+endfunction"
+        );
+        compile(input.as_bytes())
+    };
+
+    let ok = [
+        "set r = i",
+        "set r = i + r",
+        "set a = u",
+        "set a = null",
+        "set ia[i] = 1",
+        "set r = F(u)",
+        "set r = -r",
+        "if u == null then\nendif",
+        "if a == u then\nendif",
+        "loop\nexitwhen i > 1\nendloop",
+        "set c = function G",
+        "set s = s + \"a\"",
+    ];
+    for input in ok {
+        if let Err(e) = compile_body(input) {
+            panic!("{input}: {e}");
+        }
+    }
+
+    let bad = [
+        "set i = \"text\"",
+        "set i = r",
+        "set i = i + r",
+        "set u = a",
+        "set i = null",
+        "set b = null",
+        "set ia[r] = 1",
+        "set ia[1] = 1.5",
+        "set i = ia[\"s\"]",
+        "set b = -b",
+        "set r = F(1)",
+        "if i then\nendif",
+        "loop\nexitwhen i\nendloop",
+        "if i == null then\nendif",
+        "set c = function F",
+        "set c = function N",
+    ];
+    for input in bad {
+        match compile_body(input) {
+            Ok(_) => panic!("{input} should not compile"),
+            Err(e) => assert_eq!(e.kind(), ErrorKind::TypeMismatch, "{input}: {e}"),
+        }
+    }
+
+    let e = compile(b"function F takes nothing returns integer\nreturn 1.5\nendfunction")
+        .err()
+        .unwrap();
+    assert_eq!(e.kind(), ErrorKind::TypeMismatch);
+    assert_eq!(e.span().unwrap().start_line, 2);
+
+    let e = compile_body("set c = function N").err().unwrap();
+    assert!(e.message().contains("native N"), "{e}");
+    assert_eq!(e.labels()[0].span.start_line, 4);
+    Ok(())
+}
+
+#[test]
+fn test_return_path() -> Result<()> {
+    let compile_body = |body: &str| -> Result<Codegen> {
+        let input = format!("function F takes integer i returns integer\n{body}\nendfunction");
+        compile(input.as_bytes())
+    };
+
+    let ok = [
+        "return 1",
+        "if i > 0 then\nreturn 1\nelse\nreturn 2\nendif",
+        "if i > 0 then\nreturn 1\nelseif i < 0 then\nreturn 2\nelse\nreturn 0\nendif",
+        "loop\nendloop",
+        "loop\nif i > 0 then\nreturn 1\nendif\nendloop",
+        "loop\nexitwhen i > 0\nendloop\nreturn i",
+    ];
+    for body in ok {
+        let codegen = compile_body(body).unwrap_or_else(|e| panic!("{body}: {e}"));
+        assert!(codegen.warnings().is_empty(), "{body}");
+    }
+
+    let missing = [
+        "",
+        "if i > 0 then\nreturn 1\nendif",
+        "if i > 0 then\nreturn 1\nelseif i < 0 then\nreturn 2\nendif",
+        "loop\nexitwhen i > 0\nendloop",
+        "loop\nif i > 0 then\nexitwhen true\nendif\nendloop",
+        "debug return 1",
+    ];
+    for body in missing {
+        let e = compile_body(body).err().unwrap_or_else(|| panic!("{body}"));
+        assert_eq!(e.kind(), ErrorKind::MissingReturn, "{body}");
+    }
+
+    let codegen = compile_body("return 1\nset i = 2\nreturn 3")?;
+    let warnings = codegen.warnings();
+    assert_eq!(warnings.len(), 1);
+    assert_eq!(warnings[0].kind(), ErrorKind::UnreachableCode);
+    assert!(warnings[0].kind().is_warning());
+    assert_eq!(warnings[0].span().unwrap().start_line, 3);
+
+    let codegen = compile_body("loop\nendloop\nreturn 1")?;
+    assert_eq!(codegen.warnings().len(), 1);
+    let codegen = compile_body("if i > 0 then\nreturn 1\ncall F(1)\nendif\nreturn 2")?;
+    assert_eq!(codegen.warnings()[0].span().unwrap().start_line, 4);
+    Ok(())
+}
+
+#[test]
+fn test_operator_bytecode() -> Result<()> {
+    use crate::Parse;
+
+    let input =
+        b"function F takes integer a, integer b returns integer\nreturn -a + b * 2\nendfunction";
+    let file = Parse::from_slice(input).parse_file()?;
+    let mut codegen = Codegen::new();
+    codegen.file(&file)?;
+    let ops: Vec<_> = codegen
+        .program
+        .bytecodes
+        .iter()
+        .filter_map(|bytecode| match bytecode {
+            Bytecode::SetRegVar(..) => Some("var"),
+            Bytecode::SetRegLiteral(..) => Some("literal"),
+            Bytecode::Negate(..) => Some("neg"),
+            Bytecode::Add(..) => Some("add"),
+            Bytecode::Mul(..) => Some("mul"),
+            _ => None,
+        })
+        .collect();
+    // 先对a取负，再计算b * 2，最后相加
+    assert_eq!(ops, ["var", "neg", "var", "literal", "mul", "add"]);
+    Ok(())
+}
+
+#[test]
+fn test_semantic_recovery() -> Result<()> {
+    use crate::Parse;
+
+    let input = "native N takes nothing returns nothing
+constant function A takes nothing returns integer
+local integer x = 1
+loop
+set x = \"a\"
+endloop
+endfunction
+function B takes nothing returns nothing
+call N()
+set x = 1
+endfunction
+function C takes nothing returns nothing
+exitwhen true
+endfunction";
+    let file = Parse::from_slice(input.as_bytes()).parse_file()?;
+    let errors = Codegen::new().file_with_recovery(&file);
+    // A的局部变量、常量函数标记和循环都不会留给B、C
+    let kinds: Vec<_> = errors
+        .iter()
+        .map(|e| (e.kind(), e.span().unwrap().start_line))
+        .collect();
+    assert_eq!(
+        kinds,
+        [
+            (ErrorKind::TypeMismatch, 5),
+            (ErrorKind::UnknownSymbol, 10),
+            (ErrorKind::InvalidStatement, 13),
+        ]
+    );
+
+    let input = b"globals\ninteger a = \"s\"\ninteger b = 1\nconstant integer c\nreal d = 1\nendglobals\nfunction F takes nothing returns nothing\nset a = 1\nset b = 2\nset d = 3\nendfunction";
+    let file = Parse::from_slice(input).parse_file()?;
+    let errors = Codegen::new().file_with_recovery(&file);
+    let kinds: Vec<_> = errors.iter().map(|e| e.kind()).collect();
+    assert_eq!(
+        kinds,
+        [ErrorKind::TypeMismatch, ErrorKind::InvalidDefinition],
+        "{errors:?}"
+    );
+    Ok(())
+}
@@ -0,0 +1,284 @@
+use crate::Decoder;
+use crate::Error;
+use crate::FileId;
+use crate::SourceEncoding;
+use crate::Span;
+use std::fmt::Write;
+use std::sync::Arc;
+
+/// 一个源文件，记录每行的起始位置用于把字节偏移换算成行列
+pub struct SourceFile {
+    name: String,
+    text: Vec<u8>,
+    encoding: SourceEncoding,
+    lines: Vec<usize>,
+}
+
+impl SourceFile {
+    pub fn new(name: impl Into<String>, text: Vec<u8>) -> Self {
+        let lines = std::iter::once(0)
+            .chain(
+                text.iter()
+                    .enumerate()
+                    .filter(|(_, ch)| **ch == b'\n')
+                    .map(|(idx, _)| idx + 1),
+            )
+            .collect();
+        SourceFile {
+            name: name.into(),
+            text,
+            encoding: SourceEncoding::Utf8,
+            lines,
+        }
+    }
+
+    /// 渲染源码行时使用的编码，和lexer使用的编码一致
+    pub fn with_encoding(mut self, encoding: SourceEncoding) -> Self {
+        self.encoding = encoding;
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn text(&self) -> &[u8] {
+        &self.text
+    }
+
+    pub fn encoding(&self) -> SourceEncoding {
+        self.encoding
+    }
+
+    /// 字节偏移所在的行列，都从1开始，列按字节计算，和 `Span` 一致
+    pub fn line_col(&self, offset: usize) -> (usize, usize) {
+        let line = self.lines.partition_point(|start| *start <= offset) - 1;
+        (line + 1, offset - self.lines[line] + 1)
+    }
+
+    /// 第 `line` 行的内容，不包含换行符，行号从1开始
+    pub fn line(&self, line: usize) -> &[u8] {
+        let start = self.lines[line - 1];
+        let end = self.lines.get(line).copied().unwrap_or(self.text.len());
+        let text = &self.text[start..end];
+        let text = text.strip_suffix(b"\n").unwrap_or(text);
+        text.strip_suffix(b"\r").unwrap_or(text)
+    }
+}
+
+/// 编译用到的所有源文件，`FileId` 是文件加入的顺序。源码是共享的，克隆不会复制源码
+#[derive(Default, Clone)]
+pub struct SourceMap {
+    files: Vec<Arc<SourceFile>>,
+}
+
+impl SourceMap {
+    pub fn new() -> Self {
+        SourceMap::default()
+    }
+
+    pub fn add(&mut self, file: SourceFile) -> FileId {
+        self.files.push(Arc::new(file));
+        FileId(self.files.len() as u32 - 1)
+    }
+
+    pub fn file(&self, id: FileId) -> Option<&SourceFile> {
+        self.files.get(id.0 as usize).map(|file| &**file)
+    }
+
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+}
+
+const RED: &str = "\x1b[1;31m";
+const BLUE: &str = "\x1b[1;34m";
+const YELLOW: &str = "\x1b[1;33m";
+const GREEN: &str = "\x1b[1;32m";
+const BOLD: &str = "\x1b[1m";
+const RESET: &str = "\x1b[0m";
+
+/// 把错误渲染成编译器风格的文本：文件名、行列、出错的源码行和下划线，附加位置作为note
+pub struct Emitter<'a> {
+    sources: &'a SourceMap,
+    color: bool,
+}
+
+impl<'a> Emitter<'a> {
+    pub fn new(sources: &'a SourceMap) -> Self {
+        Emitter {
+            sources,
+            color: false,
+        }
+    }
+
+    /// 使用ANSI颜色，一般只在输出到终端时开启
+    pub fn with_color(mut self, color: bool) -> Self {
+        self.color = color;
+        self
+    }
+
+    pub fn render(&self, e: &Error) -> String {
+        let mut out = String::new();
+        let (severity, color) = match e.kind().is_warning() {
+            true => ("warning", YELLOW),
+            false => ("error", RED),
+        };
+        let title = format!("{severity}[{}]", e.code());
+        self.title(&mut out, color, &title, e.message());
+        let width = self.gutter_width(e);
+        if let Some(span) = e.span() {
+            self.excerpt(&mut out, span, '^', color, width);
+        }
+        for label in e.labels() {
+            self.title(&mut out, GREEN, "note", &label.message);
+            self.excerpt(&mut out, label.span, '-', GREEN, width);
+        }
+        out
+    }
+
+    fn paint(&self, out: &mut String, color: &str, text: &str) {
+        if self.color {
+            let _ = write!(out, "{color}{text}{RESET}");
+        } else {
+            out.push_str(text);
+        }
+    }
+
+    fn title(&self, out: &mut String, color: &str, title: &str, message: &str) {
+        self.paint(out, color, title);
+        self.paint(out, BOLD, &format!(": {message}"));
+        out.push('\n');
+    }
+
+    /// 行号栏的宽度，同一个错误的所有源码片段对齐
+    fn gutter_width(&self, e: &Error) -> usize {
+        e.span()
+            .into_iter()
+            .chain(e.labels().iter().map(|label| label.span))
+            .filter_map(|span| {
+                let file = self.sources.file(span.file)?;
+                Some(file.line_col(span.start).0.to_string().len())
+            })
+            .max()
+            .unwrap_or(1)
+    }
+
+    fn excerpt(&self, out: &mut String, span: Span, mark: char, color: &str, width: usize) {
+        let pad = " ".repeat(width);
+        self.paint(out, BLUE, &format!("{pad}--> "));
+        let Some(file) = self.sources.file(span.file) else {
+            // 没有源码时只能输出lexer记录的行列
+            out.push_str(&format!("{span}\n"));
+            return;
+        };
+        let (line, col) = file.line_col(span.start);
+        out.push_str(&format!("{}:{line}:{col}\n", file.name()));
+
+        let text = file.line(line);
+        let mut decoder = Decoder::new(file.encoding);
+        let prefix_len = (col - 1).min(text.len());
+        let prefix = decoder.decode(&text[..prefix_len]);
+        // 多行的区间只标记第一行
+        let end = (span.end.max(span.start + 1) - span.start).min(text.len() - prefix_len);
+        let marked = decoder.decode(&text[prefix_len..prefix_len + end]);
+        let source = decoder.decode(text);
+
+        self.paint(out, BLUE, &format!("{pad} |\n"));
+        self.paint(out, BLUE, &format!("{line:>width$} | "));
+        out.push_str(&source);
+        out.push('\n');
+        self.paint(out, BLUE, &format!("{pad} | "));
+        let indent: String = prefix
+            .chars()
+            .map(|ch| if ch == '\t' { '\t' } else { ' ' })
+            .collect();
+        out.push_str(&indent);
+        let marks = mark.to_string().repeat(marked.chars().count().max(1));
+        self.paint(out, color, &marks);
+        out.push('\n');
+    }
+}
+
+#[test]
+fn test_line_col() {
+    let file = SourceFile::new("a.j", b"globals\r\n integer a\nendglobals".to_vec());
+    assert_eq!(file.line_col(0), (1, 1));
+    assert_eq!(file.line_col(10), (2, 2));
+    assert_eq!(file.line_col(20), (3, 1));
+    assert_eq!(file.line(1), b"globals");
+    assert_eq!(file.line(3), b"endglobals");
+}
+
+#[test]
+fn test_render() -> crate::Result<()> {
+    use crate::Parse;
+
+    let input = b"function F takes integer a returns nothing\nendfunction\nfunction Main takes nothing returns nothing\n\tcall F(1, 2)\nendfunction\n";
+    let mut sources = SourceMap::new();
+    sources.add(SourceFile::new("main.j", input.to_vec()));
+    let err = Parse::from_slice(input).file().unwrap_err();
+    let text = Emitter::new(&sources).render(&err);
+    assert_eq!(
+        text,
+        "error[E0402]: function F takes 1 params, but 2 given
+ --> main.j:4:12
+  |
+4 | \tcall F(1, 2)
+  | \t          ^
+note: function declared here
+ --> main.j:1:10
+  |
+1 | function F takes integer a returns nothing
+  |          -
+"
+    );
+
+    let colored = Emitter::new(&sources).with_color(true).render(&err);
+    assert!(colored.starts_with("\x1b[1;31merror[E0402]\x1b[0m"));
+    Ok(())
+}
+
+#[test]
+fn test_render_multiple_files() {
+    let mut sources = SourceMap::new();
+    let common = sources.add(SourceFile::new(
+        "common.j",
+        b"native F takes nothing returns nothing\n".to_vec(),
+    ));
+    let main = sources.add(SourceFile::new(
+        "main.j",
+        b"\n\n\n\n\n\n\n\n\n\nnative F takes nothing returns nothing".to_vec(),
+    ));
+    let span = |file, start| Span {
+        file,
+        start,
+        end: start + 1,
+        ..Span::default()
+    };
+    let err = Error::spanned(
+        crate::ErrorKind::DuplicateDefinition,
+        span(main, 17),
+        "duplicate",
+    )
+    .with_label(span(common, 7), "first defined here");
+    let text = Emitter::new(&sources).render(&err);
+    assert_eq!(
+        text,
+        "error[E0303]: duplicate
+  --> main.j:11:8
+   |
+11 | native F takes nothing returns nothing
+   |        ^
+note: first defined here
+  --> common.j:1:8
+   |
+ 1 | native F takes nothing returns nothing
+   |        -
+"
+    );
+}
@@ -0,0 +1,185 @@
+use encoding_rs::{Encoding, BIG5, GBK, UTF_8, WINDOWS_1252};
+use std::str::FromStr;
+
+/// 源码文本编码，字符串字面量、注释和名字都按这个编码解码
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SourceEncoding {
+    #[default]
+    Utf8,
+    Gbk,
+    Big5,
+    Cp1252,
+    /// 合法的UTF-8按UTF-8处理，否则在GBK、Big5中选择解码出常用汉字最多的，都失败时使用CP1252
+    Auto,
+}
+
+impl SourceEncoding {
+    fn encoding(&self) -> Option<&'static Encoding> {
+        match self {
+            SourceEncoding::Utf8 => Some(UTF_8),
+            SourceEncoding::Gbk => Some(GBK),
+            SourceEncoding::Big5 => Some(BIG5),
+            SourceEncoding::Cp1252 => Some(WINDOWS_1252),
+            SourceEncoding::Auto => None,
+        }
+    }
+}
+
+impl FromStr for SourceEncoding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let encoding = match s.to_ascii_lowercase().as_str() {
+            "utf8" | "utf-8" => SourceEncoding::Utf8,
+            "gbk" | "cp936" => SourceEncoding::Gbk,
+            "big5" | "cp950" => SourceEncoding::Big5,
+            "cp1252" | "windows-1252" => SourceEncoding::Cp1252,
+            "auto" => SourceEncoding::Auto,
+            _ => return Err(format!("unknown encoding: {s}")),
+        };
+        Ok(encoding)
+    }
+}
+
+/// 按源码编码解码字节，`Auto` 在 `detect` 或第一次遇到非UTF-8内容时确定整个文件的编码
+#[derive(Debug, Clone)]
+pub struct Decoder {
+    encoding: SourceEncoding,
+    detected: Option<&'static Encoding>,
+}
+
+impl Decoder {
+    pub fn new(encoding: SourceEncoding) -> Self {
+        Decoder {
+            encoding,
+            detected: encoding.encoding(),
+        }
+    }
+
+    /// 实际使用的编码，`Auto` 还没有遇到非UTF-8内容时返回 `Auto`
+    pub fn encoding(&self) -> SourceEncoding {
+        match self.detected {
+            Some(e) if e == UTF_8 => SourceEncoding::Utf8,
+            Some(e) if e == GBK => SourceEncoding::Gbk,
+            Some(e) if e == BIG5 => SourceEncoding::Big5,
+            Some(e) if e == WINDOWS_1252 => SourceEncoding::Cp1252,
+            _ => self.encoding,
+        }
+    }
+
+    pub fn decode(&mut self, bytes: &[u8]) -> String {
+        if bytes.is_ascii() {
+            return String::from_utf8(bytes.to_vec()).unwrap();
+        }
+        let encoding = self.encoding_for(bytes);
+        encoding.decode_without_bom_handling(bytes).0.into_owned()
+    }
+
+    /// 和 `decode` 一样，但是有不能按源码编码解码的字节时返回 `None`，不替换成U+FFFD
+    pub fn decode_exact(&mut self, bytes: &[u8]) -> Option<String> {
+        if bytes.is_ascii() {
+            return Some(String::from_utf8(bytes.to_vec()).unwrap());
+        }
+        let encoding = self.encoding_for(bytes);
+        let text = encoding.decode_without_bom_handling_and_without_replacement(bytes)?;
+        Some(text.into_owned())
+    }
+
+    /// `Auto` 还没有确定编码时，合法的UTF-8按UTF-8解码，否则按 `bytes` 确定整个文件的编码
+    fn encoding_for(&mut self, bytes: &[u8]) -> &'static Encoding {
+        match self.detected {
+            Some(encoding) => encoding,
+            None if std::str::from_utf8(bytes).is_ok() => UTF_8,
+            None => self.detected.insert(detect(bytes)),
+        }
+    }
+
+    /// 按整个文件确定 `Auto` 的编码，全是ASCII时保持 `Auto`
+    pub fn detect(&mut self, bytes: &[u8]) {
+        if self.detected.is_some() || bytes.is_ascii() {
+            return;
+        }
+        self.detected = match std::str::from_utf8(bytes) {
+            Ok(_) => Some(UTF_8),
+            Err(_) => Some(detect(bytes)),
+        };
+    }
+}
+
+/// GBK的字节范围包含了Big5，不能只看能否解码。
+/// 按解码出的汉字和全角标点个数打分，错误的编码会解码出大量符号和生僻区的字符，分数相同时优先GBK
+fn detect(bytes: &[u8]) -> &'static Encoding {
+    let mut best = None;
+    for encoding in [GBK, BIG5] {
+        let Some(text) = encoding.decode_without_bom_handling_and_without_replacement(bytes) else {
+            continue;
+        };
+        let score = text
+            .chars()
+            .filter(|ch| {
+                matches!(
+                    ch,
+                    '\u{4E00}'..='\u{9FFF}' | '\u{3000}'..='\u{303F}' | '\u{FF00}'..='\u{FFEF}'
+                )
+            })
+            .count();
+        if best.is_none_or(|(_, best)| score > best) {
+            best = Some((encoding, score));
+        }
+    }
+    best.map_or(WINDOWS_1252, |(encoding, _)| encoding)
+}
+
+#[test]
+fn test_decode() {
+    let mut decoder = Decoder::new(SourceEncoding::Gbk);
+    assert_eq!(decoder.decode(&[0xC4, 0xE3, 0xBA, 0xC3]), "你好");
+
+    let mut decoder = Decoder::new(SourceEncoding::Big5);
+    assert_eq!(decoder.decode(&[0xA7, 0x41, 0xA6, 0x6E]), "你好");
+
+    let mut decoder = Decoder::new(SourceEncoding::Cp1252);
+    assert_eq!(decoder.decode(b"caf\xE9"), "café");
+
+    let mut decoder = Decoder::new(SourceEncoding::Utf8);
+    assert_eq!(decoder.decode("你好".as_bytes()), "你好");
+    assert_eq!(decoder.decode(b"a\xFF"), "a\u{FFFD}");
+    assert_eq!(decoder.decode_exact(b"a\xFF"), None);
+    assert_eq!(
+        decoder.decode_exact("你好".as_bytes()).as_deref(),
+        Some("你好")
+    );
+}
+
+#[test]
+fn test_auto_detect() {
+    let mut decoder = Decoder::new(SourceEncoding::Auto);
+    assert_eq!(decoder.decode("你好".as_bytes()), "你好");
+    assert_eq!(decoder.encoding(), SourceEncoding::Auto);
+    assert_eq!(decoder.decode(&[0xC4, 0xE3, 0xBA, 0xC3]), "你好");
+    assert_eq!(decoder.encoding(), SourceEncoding::Gbk);
+
+    let mut decoder = Decoder::new(SourceEncoding::Auto);
+    assert_eq!(decoder.decode(&[0xA7, 0x41, 0xA6, 0x6E]), "你好");
+    assert_eq!(decoder.encoding(), SourceEncoding::Big5);
+
+    // 繁体中文的句子，每个字都能按GBK解码
+    let big5 = [
+        0xA7, 0x41, 0xA6, 0x6E, 0xA1, 0x41, 0xA5, 0x40, 0xAC, 0xC9, 0xA1, 0x43,
+    ];
+    let mut decoder = Decoder::new(SourceEncoding::Auto);
+    assert_eq!(decoder.decode(&big5), "你好，世界。");
+    assert_eq!(decoder.encoding(), SourceEncoding::Big5);
+
+    let mut decoder = Decoder::new(SourceEncoding::Auto);
+    assert_eq!(decoder.decode("你好，世界。".as_bytes()), "你好，世界。");
+    let gbk = [
+        0xC4, 0xE3, 0xBA, 0xC3, 0xA3, 0xAC, 0xCA, 0xC0, 0xBD, 0xE7, 0xA1, 0xA3,
+    ];
+    assert_eq!(decoder.decode(&gbk), "你好，世界。");
+    assert_eq!(decoder.encoding(), SourceEncoding::Gbk);
+
+    let mut decoder = Decoder::new(SourceEncoding::Auto);
+    assert_eq!(decoder.decode(b"caf\xE9 au lait"), "café au lait");
+    assert_eq!(decoder.encoding(), SourceEncoding::Cp1252);
+}
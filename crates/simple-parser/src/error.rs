@@ -1,11 +1,166 @@
+use crate::Span;
+use std::fmt::Display;
+
 pub type Result<T> = core::result::Result<T, Error>;
-pub type Error = Box<dyn std::error::Error>;
 
-// #[derive(Debug, From)]
-// pub enum Error {
-//     LexError {
-//         raw_number: u32,
-//         line_number: u32
-//     }
+/// 错误种类，每种都有稳定的错误码，工具应该匹配种类而不是错误信息
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    /// 读取源码失败
+    Io,
+    /// 增量分词的编辑和旧token流对不上
+    InvalidEdit,
+    /// 编译单元没有全部编译成功，不能做成预编译环境
+    NotCompiled,
+    /// 非法字符、未结束的字符串、错误的数字字面量等词法错误
+    Lex,
+    /// 不符合文法的token
+    Syntax,
+    /// 没有定义的变量或函数
+    UnknownSymbol,
+    /// 没有定义的类型
+    UnknownType,
+    /// 函数重复定义
+    DuplicateDefinition,
+    /// 定义本身不合法，如参数过多、数组变量初始化
+    InvalidDefinition,
+    /// 类型不匹配
+    TypeMismatch,
+    /// 函数调用的参数个数不对
+    ArityMismatch,
+    /// 语句出现在不允许的位置，如循环外的 `exitwhen`
+    InvalidStatement,
+    /// 给常量赋值
+    AssignToConstant,
+    /// 常量函数调用了非常量函数
+    NonConstantCall,
+    /// 有返回值的函数存在不返回的执行路径
+    MissingReturn,
+    /// 永远执行不到的语句，只是警告
+    UnreachableCode,
+}
+
+impl ErrorKind {
+    /// 警告不影响编译结果
+    pub fn is_warning(&self) -> bool {
+        matches!(self, ErrorKind::UnreachableCode)
+    }
+
+    /// 稳定的错误码，新增种类只会追加新的错误码
+    pub fn code(&self) -> &'static str {
+        match self {
+            ErrorKind::Io => "E0001",
+            ErrorKind::InvalidEdit => "E0002",
+            ErrorKind::NotCompiled => "E0003",
+            ErrorKind::Lex => "E0101",
+            ErrorKind::Syntax => "E0201",
+            ErrorKind::UnknownSymbol => "E0301",
+            ErrorKind::UnknownType => "E0302",
+            ErrorKind::DuplicateDefinition => "E0303",
+            ErrorKind::InvalidDefinition => "E0304",
+            ErrorKind::TypeMismatch => "E0401",
+            ErrorKind::ArityMismatch => "E0402",
+            ErrorKind::InvalidStatement => "E0501",
+            ErrorKind::AssignToConstant => "E0502",
+            ErrorKind::NonConstantCall => "E0503",
+            ErrorKind::MissingReturn => "E0504",
+            ErrorKind::UnreachableCode => "W0001",
+        }
+    }
+}
+
+/// 错误的附加位置，如重复定义时第一次定义的位置
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Label {
+    pub span: Span,
+    pub message: String,
+}
+
+/// 编译错误，带有种类、主要位置和可选的附加位置
+#[derive(Debug, Clone)]
+pub struct Error(Box<ErrorInner>);
+
+#[derive(Debug, Clone)]
+struct ErrorInner {
+    kind: ErrorKind,
+    message: String,
+    span: Option<Span>,
+    labels: Vec<Label>,
+}
+
+impl Error {
+    /// 还没有位置的错误，lexer会补上当前token的位置
+    pub fn new(kind: ErrorKind, message: impl Into<String>) -> Self {
+        Error(Box::new(ErrorInner {
+            kind,
+            message: message.into(),
+            span: None,
+            labels: vec![],
+        }))
+    }
+
+    pub fn spanned(kind: ErrorKind, span: Span, message: impl Into<String>) -> Self {
+        Error::new(kind, message).with_span(span)
+    }
+
+    pub fn with_span(mut self, span: Span) -> Self {
+        self.0.span = Some(span);
+        self
+    }
+
+    pub fn with_label(mut self, span: Span, message: impl Into<String>) -> Self {
+        self.0.labels.push(Label {
+            span,
+            message: message.into(),
+        });
+        self
+    }
+
+    /// 换算主要位置和附加位置，如把拼接后的位置换算回各自的文件
+    pub fn map_spans(mut self, f: impl Fn(Span) -> Span) -> Self {
+        self.0.span = self.0.span.map(&f);
+        for label in &mut self.0.labels {
+            label.span = f(label.span);
+        }
+        self
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        self.0.kind
+    }
+
+    pub fn code(&self) -> &'static str {
+        self.0.kind.code()
+    }
+
+    pub fn message(&self) -> &str {
+        &self.0.message
+    }
+
+    /// 主要位置，io错误没有位置
+    pub fn span(&self) -> Option<Span> {
+        self.0.span
+    }
+
+    pub fn labels(&self) -> &[Label] {
+        &self.0.labels
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.0.span {
+            Some(span) => write!(f, "{span}: {}", self.0.message),
+            None => write!(f, "{}", self.0.message),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
 
-// }
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Error::new(ErrorKind::Io, e.to_string())
+    }
+}
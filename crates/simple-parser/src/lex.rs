@@ -1,8 +1,14 @@
+use crate::Decoder;
+use crate::Error;
+use crate::ErrorKind;
+use crate::FileId;
 use crate::Result;
-use std::{
-    io::{Bytes, Read},
-    mem,
-};
+use crate::SourceEncoding;
+use crate::Span;
+use std::collections::HashSet;
+use std::io::{BufReader, Bytes, Read};
+use std::ops::Range;
+use std::sync::Arc;
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Token {
@@ -37,6 +43,7 @@ pub enum Token {
     True,
     False,
     Null,
+    Debug,
 
     // arithmetic sign
     Add,
@@ -60,22 +67,132 @@ pub enum Token {
 
     Integer(i64),
     Float(f64),
-    String(Vec<u8>),
+    /// 单引号包裹的id，如 `'hfoo'`，`text` 为引号之间的原文
+    RawCode {
+        value: u32,
+        text: Vec<u8>,
+    },
+    /// `value` 是处理转义后的内容，`raw` 是两个引号之间的原始源码
+    String {
+        value: Vec<u8>,
+        raw: Vec<u8>,
+    },
 
-    Name(String),
+    /// 同一个lexer产生的相同名字共享同一份内存
+    Name(Arc<str>),
 
+    /// 换行，只在 `with_newlines(true)` 时产生，`\r\n` 视为一个换行
+    Newline,
+    /// 无法识别的片段，只在 `with_recovery(true)` 时产生
+    Error,
     Eos,
 }
 
 impl Eq for Token {}
 
+/// 带有源码位置的token
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpannedToken {
+    pub token: Token,
+    pub span: Span,
+    /// token之前的空白、换行和注释，只在 `with_trivia(true)` 时记录
+    pub trivia: Vec<Trivia>,
+    /// token的原文，只在 `with_trivia(true)` 时记录
+    pub text: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TriviaKind {
+    Whitespace,
+    Newline,
+    LineComment,
+    BlockComment,
+}
+
+/// 不影响语法的源码片段
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Trivia {
+    pub kind: TriviaKind,
+    pub span: Span,
+    pub text: Vec<u8>,
+}
+
+/// 用 `with_trivia(true)` 得到的token流逐字节还原源码
+pub fn source_text(tokens: &[SpannedToken]) -> Vec<u8> {
+    let mut text = vec![];
+    for token in tokens {
+        for trivia in &token.trivia {
+            text.extend_from_slice(&trivia.text);
+        }
+        text.extend_from_slice(&token.text);
+    }
+    text
+}
+
+/// 一次文本编辑，把旧源码中 `range` 的字节替换为 `text`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextEdit {
+    pub range: Range<usize>,
+    pub text: Vec<u8>,
+}
+
+impl TextEdit {
+    pub fn new(range: Range<usize>, text: impl Into<Vec<u8>>) -> Self {
+        TextEdit {
+            range,
+            text: text.into(),
+        }
+    }
+
+    /// 在旧源码上应用编辑，得到新源码
+    pub fn apply(&self, source: &[u8]) -> Vec<u8> {
+        let mut text = source[..self.range.start].to_vec();
+        text.extend_from_slice(&self.text);
+        text.extend_from_slice(&source[self.range.end..]);
+        text
+    }
+}
+
 type StdIoResult = std::result::Result<u8, std::io::Error>;
 
-pub struct Lex<R: Read> {
-    input: CodeRead<Bytes<R>>,
-    ahead: Token,
+pub struct Lex<S: Source> {
+    input: S,
+    file: FileId,
+    ahead: Option<SpannedToken>,
+    span: Span,
+    newlines: bool,
+    trivia: bool,
+    captured: Vec<u8>,
+    pending: Vec<Trivia>,
+    recovery: bool,
+    errors: Vec<Error>,
+    decoder: Decoder,
+    names: HashSet<Arc<str>>,
+    buffer: Vec<u8>,
+}
+
+/// lexer的字节输入，位置只在字节被消费时推进
+pub trait Source {
+    fn next_byte(&mut self) -> Result<Option<u8>>;
+    fn peek_byte(&mut self) -> Result<Option<u8>>;
+    /// 已消费的字节数
+    fn num(&self) -> usize;
+    /// 当前行，从0开始
+    fn line(&self) -> usize;
+    /// 当前列，从0开始
+    fn col(&self) -> usize;
+    /// 从 `start` 到当前位置已消费的字节，只有内存中的输入可以直接借出
+    fn consumed(&self, _start: usize) -> Option<&[u8]> {
+        None
+    }
+    /// 内存中的全部输入，用于在分词前确定 `Auto` 的编码
+    fn input(&self) -> Option<&[u8]> {
+        None
+    }
 }
 
+pub type ReadSource<R> = CodeRead<Bytes<BufReader<R>>>;
+
 pub struct CodeRead<I>
 where
     I: Iterator<Item = StdIoResult>,
@@ -120,77 +237,410 @@ fn record(
 impl<I: Iterator<Item = StdIoResult>> Iterator for CodeRead<I> {
     type Item = I::Item;
 
+    /// 位置只在字节被消费时记录，peek不会推进行列
     fn next(&mut self) -> Option<Self::Item> {
-        match self.peeked.take() {
+        let res = match self.peeked.take() {
             Some(v) => v,
-            None => {
-                let res: Option<std::result::Result<u8, std::io::Error>> = self.inner.next();
-                record(&mut self.num, &mut self.line, &mut self.col, res)
-            }
-        }
+            None => self.inner.next(),
+        };
+        record(&mut self.num, &mut self.line, &mut self.col, res)
     }
 }
 
 impl<I: Iterator<Item = StdIoResult>> CodeRead<I> {
     fn peek(&mut self) -> Option<&I::Item> {
         self.peeked
-            .get_or_insert_with(|| {
-                let res = self.inner.next();
-                record(&mut self.num, &mut self.line, &mut self.col, res)
-            })
+            .get_or_insert_with(|| self.inner.next())
             .as_ref()
     }
 }
 
-impl<R: Read> Lex<R> {
+impl<I: Iterator<Item = StdIoResult>> Source for CodeRead<I> {
+    fn next_byte(&mut self) -> Result<Option<u8>> {
+        let res = self.next().transpose()?;
+        Ok(res)
+    }
+
+    fn peek_byte(&mut self) -> Result<Option<u8>> {
+        match self.peek() {
+            Some(Ok(byte)) => Ok(Some(*byte)),
+            Some(Err(_)) => Err(self.next().unwrap().unwrap_err().into()),
+            None => Ok(None),
+        }
+    }
+
+    fn num(&self) -> usize {
+        self.num
+    }
+
+    fn line(&self) -> usize {
+        self.line
+    }
+
+    fn col(&self) -> usize {
+        self.col
+    }
+}
+
+/// 内存中的源码，不需要逐字节处理io错误，名字可以直接从输入中借出
+pub struct SliceRead<'a> {
+    input: &'a [u8],
+    num: usize,
+    line: usize,
+    col: usize,
+}
+
+impl<'a> SliceRead<'a> {
+    pub fn new(input: &'a [u8]) -> Self {
+        SliceRead {
+            input,
+            num: 0,
+            line: 0,
+            col: 0,
+        }
+    }
+}
+
+impl Source for SliceRead<'_> {
+    #[inline]
+    fn next_byte(&mut self) -> Result<Option<u8>> {
+        let Some(&byte) = self.input.get(self.num) else {
+            return Ok(None);
+        };
+        self.num += 1;
+        self.col += 1;
+        if byte == b'\n' {
+            self.line += 1;
+            self.col = 0;
+        }
+        Ok(Some(byte))
+    }
+
+    #[inline]
+    fn peek_byte(&mut self) -> Result<Option<u8>> {
+        Ok(self.input.get(self.num).copied())
+    }
+
+    fn num(&self) -> usize {
+        self.num
+    }
+
+    fn line(&self) -> usize {
+        self.line
+    }
+
+    fn col(&self) -> usize {
+        self.col
+    }
+
+    fn consumed(&self, start: usize) -> Option<&[u8]> {
+        Some(&self.input[start..self.num])
+    }
+
+    fn input(&self) -> Option<&[u8]> {
+        Some(self.input)
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Pos {
+    num: usize,
+    line: usize,
+    col: usize,
+}
+
+impl<R: Read> Lex<ReadSource<R>> {
     pub fn new(input: R) -> Self {
+        Lex::with_file(input, FileId::default())
+    }
+
+    pub fn with_file(input: R, file: FileId) -> Self {
+        Lex::from_source(CodeRead::new(BufReader::new(input).bytes()), file)
+    }
+}
+
+impl<'a> Lex<SliceRead<'a>> {
+    /// 直接在内存中的源码上分词，比 `Lex::new` 快很多
+    pub fn from_slice(input: &'a [u8], file: FileId) -> Self {
+        Lex::from_source(SliceRead::new(input), file)
+    }
+
+    /// 增量分词，`self` 是编辑后源码上的lexer，`previous` 是相同配置的lexer在编辑前源码上
+    /// 得到的完整token流（以 `Eos` 结尾）。只重新扫描受编辑影响的部分，其余token平移位置后复用，
+    /// 结果和重新完整分词一致。`errors()` 只包含重新扫描部分的错误
+    pub fn relex(
+        mut self,
+        previous: &[SpannedToken],
+        edit: &TextEdit,
+    ) -> Result<Vec<SpannedToken>> {
+        let old_len = previous.last().map(|token| token.span.end).unwrap_or(0);
+        let new_len = self.input.input.len();
+        if edit.range.start > edit.range.end
+            || edit.range.end > old_len
+            || old_len - edit.range.len() + edit.text.len() != new_len
+        {
+            return Err(Error::new(
+                ErrorKind::InvalidEdit,
+                format!(
+                    "text edit {:?} does not match the previous tokens",
+                    edit.range
+                ),
+            ));
+        }
+
+        // lexer最多向后看一个字节，在编辑点之前结束的token不受影响
+        let keep = previous.partition_point(|token| token.span.end < edit.range.start);
+        let mut tokens = previous[..keep].to_vec();
+        if let Some(last) = tokens.last() {
+            self.input.num = last.span.end;
+            self.input.line = last.span.end_line - 1;
+            self.input.col = last.span.end_col - 1;
+            self.span = last.span;
+        }
+
+        // 新token在编辑区之后、且和旧token流在同一位置结束时，剩下的token必然相同
+        let edited = edit.range.start + edit.text.len();
+        loop {
+            let token = self.next_spanned()?;
+            let span = token.span;
+            let eos = token.token == Token::Eos;
+            tokens.push(token);
+            if eos {
+                return Ok(tokens);
+            }
+            if span.end < edited {
+                continue;
+            }
+            let old_end = span.end - edited + edit.range.end;
+            let idx = previous.partition_point(|token| token.span.end < old_end);
+            let Some(old) = previous.get(idx) else {
+                continue;
+            };
+            if old.span.end != old_end || old.token == Token::Eos {
+                continue;
+            }
+            for token in &previous[idx + 1..] {
+                let mut token = token.clone();
+                shift_span(&mut token.span, &old.span, &span);
+                for trivia in &mut token.trivia {
+                    shift_span(&mut trivia.span, &old.span, &span);
+                }
+                tokens.push(token);
+            }
+            return Ok(tokens);
+        }
+    }
+}
+
+/// 把同步点之后的旧区间平移到新源码中，`old` 和 `new` 是在同一位置结束的旧、新token
+fn shift_span(span: &mut Span, old: &Span, new: &Span) {
+    let shift = |line: usize, col: usize| {
+        if line == old.end_line {
+            (new.end_line, col - old.end_col + new.end_col)
+        } else {
+            (line - old.end_line + new.end_line, col)
+        }
+    };
+    span.start = span.start - old.end + new.end;
+    span.end = span.end - old.end + new.end;
+    (span.start_line, span.start_col) = shift(span.start_line, span.start_col);
+    (span.end_line, span.end_col) = shift(span.end_line, span.end_col);
+}
+
+impl<S: Source> Lex<S> {
+    pub fn from_source(input: S, file: FileId) -> Self {
         Lex {
-            input: CodeRead::new(input.bytes()),
-            ahead: Token::Eos,
+            input,
+            file,
+            ahead: None,
+            span: Span {
+                file,
+                start_line: 1,
+                start_col: 1,
+                end_line: 1,
+                end_col: 1,
+                ..Span::default()
+            },
+            newlines: false,
+            trivia: false,
+            captured: vec![],
+            pending: vec![],
+            recovery: false,
+            errors: vec![],
+            decoder: Decoder::new(SourceEncoding::Utf8),
+            names: HashSet::new(),
+            buffer: vec![],
         }
     }
 
+    /// 源码编码，名字按这个编码解码，字符串字面量保留原始字节。
+    /// 内存中的输入在这里就按整个文件确定 `Auto` 的编码，分词时才能识别GBK、Big5的双字节字符
+    pub fn with_encoding(mut self, encoding: SourceEncoding) -> Self {
+        self.decoder = Decoder::new(encoding);
+        if let Some(input) = self.input.input() {
+            self.decoder.detect(input);
+        }
+        self
+    }
+
+    pub fn encoding(&self) -> SourceEncoding {
+        self.decoder.encoding()
+    }
+
+    /// 按源码编码解码字符串字面量、注释等原始字节
+    pub fn decode(&mut self, bytes: &[u8]) -> String {
+        self.decoder.decode(bytes)
+    }
+
+    /// 开启后遇到词法错误不再中断，而是产生 `Token::Error` 并把错误记录到 `errors`
+    pub fn with_recovery(mut self, recovery: bool) -> Self {
+        self.recovery = recovery;
+        self
+    }
+
+    /// 开启后记录每个token的原文和它之前的trivia，可以无损还原源码
+    pub fn with_trivia(mut self, trivia: bool) -> Self {
+        self.trivia = trivia;
+        self
+    }
+
+    /// 开启后换行不再被当作空白跳过，而是产生 `Token::Newline`
+    pub fn with_newlines(mut self, newlines: bool) -> Self {
+        self.newlines = newlines;
+        self
+    }
+
+    pub fn file(&self) -> FileId {
+        self.file
+    }
+
     pub fn num(&self) -> usize {
-        self.input.num
+        self.input.num()
     }
 
     pub fn line(&self) -> usize {
-        self.input.line
+        self.input.line()
     }
 
     pub fn col(&self) -> usize {
-        self.input.col
+        self.input.col()
+    }
+
+    /// 最近一次 `next` 返回的token的位置
+    pub fn span(&self) -> Span {
+        self.span
     }
 
     pub fn peek(&mut self) -> Result<&Token> {
-        if self.ahead == Token::Eos {
-            self.ahead = self.next_token()?;
-        }
-        Ok(&self.ahead)
+        Ok(&self.peek_spanned()?.token)
     }
 
+    #[allow(clippy::should_implement_trait)]
     pub fn next(&mut self) -> Result<Token> {
-        let f = if self.ahead == Token::Eos {
-            self.next_token()?
-        } else {
-            mem::replace(&mut self.ahead, Token::Eos)
+        Ok(self.next_spanned()?.token)
+    }
+
+    pub fn peek_spanned(&mut self) -> Result<&SpannedToken> {
+        if self.ahead.is_none() {
+            self.ahead = Some(self.next_token()?);
+        }
+        Ok(self.ahead.as_ref().unwrap())
+    }
+
+    pub fn next_spanned(&mut self) -> Result<SpannedToken> {
+        let f = match self.ahead.take() {
+            Some(f) => f,
+            None => self.next_token()?,
         };
+        self.span = f.span;
         Ok(f)
     }
 
+    fn pos(&self) -> Pos {
+        Pos {
+            num: self.input.num(),
+            line: self.input.line(),
+            col: self.input.col(),
+        }
+    }
+
+    fn span_from(&self, start: Pos) -> Span {
+        let end = self.pos();
+        Span {
+            file: self.file,
+            start: start.num,
+            end: end.num,
+            start_line: start.line + 1,
+            start_col: start.col + 1,
+            end_line: end.line + 1,
+            end_col: end.col + 1,
+        }
+    }
+
+    /// 恢复模式下记录词法错误并返回 `Token::Error`，否则直接返回带位置的错误
+    fn recover(&mut self, start: Pos, res: Result<Token>) -> Result<Token> {
+        let e = match res {
+            Ok(token) => return Ok(token),
+            Err(e) => e,
+        };
+        if e.kind() == ErrorKind::Io || !self.recovery {
+            return Err(self.located(start, e));
+        }
+        let e = self.located(start, e);
+        self.errors.push(e);
+        Ok(Token::Error)
+    }
+
+    /// 恢复模式下记录的所有词法错误
+    pub fn errors(&self) -> &[Error] {
+        &self.errors
+    }
+
+    pub fn take_errors(&mut self) -> Vec<Error> {
+        std::mem::take(&mut self.errors)
+    }
+
+    /// 给还没有位置的词法错误补上当前token的位置
+    fn located(&self, start: Pos, e: Error) -> Error {
+        if e.span().is_some() || e.kind() == ErrorKind::Io {
+            return e;
+        }
+        e.with_span(self.span_from(start))
+    }
+
+    #[inline]
     fn next_byte(&mut self) -> Result<Option<u8>> {
-        let res = self.input.next().transpose()?;
+        let res = self.input.next_byte()?;
+        if self.trivia {
+            if let Some(byte) = res {
+                self.captured.push(byte);
+            }
+        }
         Ok(res)
     }
 
-    fn peek_byte(&mut self) -> Result<Option<u8>> {
-        match self.input.peek() {
-            Some(Ok(byte)) => Ok(Some(*byte)),
-            Some(Err(e)) => Err(e.to_string().into()),
-            None => Ok(None),
+    fn push_trivia(&mut self, kind: TriviaKind, start: Pos) {
+        if self.trivia {
+            let span = self.span_from(start);
+            let text = std::mem::take(&mut self.captured);
+            self.pending.push(Trivia { kind, span, text });
         }
     }
 
+    fn spanned(&mut self, token: Token, start: Pos) -> SpannedToken {
+        SpannedToken {
+            token,
+            span: self.span_from(start),
+            trivia: std::mem::take(&mut self.pending),
+            text: std::mem::take(&mut self.captured),
+        }
+    }
+
+    #[inline]
+    fn peek_byte(&mut self) -> Result<Option<u8>> {
+        self.input.peek_byte()
+    }
+
     fn guess_byte(&mut self, ch: u8) -> Result<bool> {
         let byte = self.peek_byte()?;
         Ok(byte == Some(ch))
@@ -206,42 +656,135 @@ impl<R: Read> Lex<R> {
 
     pub fn expect(&mut self, ch: u8) -> Result<()> {
         if !self.guess_byte(ch)? {
-            return Err(format!("expect {}", ch as char).into());
+            return lex_err(format!("expect {}", ch as char));
         }
         Ok(())
     }
 }
 
-impl<R: Read> Lex<R> {
-    ///! 读取字符串类型字面量
+#[rustfmt::skip]
+fn is_token_start(ch: u8) -> bool {
+    matches!(
+        ch,
+        b' ' | b'\t' | b'\n' | b'\r' | b'0'..=b'9' | b'a'..=b'z' | b'A'..=b'Z' | b'_'
+        | b'$' | b'.' | b'\'' | b'\"' | b'+' | b'-' | b'*' | b'/' | b'=' | b'!' | b'<'
+        | b'>' | b',' | b'(' | b')' | b'[' | b']'
+    )
+}
+
+fn lex_err<T>(message: impl Into<String>) -> Result<T> {
+    Err(Error::new(ErrorKind::Lex, message))
+}
+
+/// 整数字面量必须能放进虚拟机的32位整数，超过 `i32::MAX` 的值按补码解释
+fn integer_literal(digits: &str, radix: u32) -> Result<Token> {
+    if digits.is_empty() {
+        return lex_err("missing digits in integer literal");
+    }
+    if let Some(ch) = digits.chars().find(|ch| !ch.is_digit(radix)) {
+        return lex_err(format!("invalid digit '{ch}' in integer literal"));
+    }
+    match u64::from_str_radix(digits, radix) {
+        Ok(value) if value <= u32::MAX as u64 => Ok(Token::Integer(value as i64)),
+        _ => lex_err("integer literal out of 32-bit range"),
+    }
+}
+
+impl<S: Source> Lex<S> {
+    /// 读取字符串类型字面量，支持 `\b \t \n \f \r \" \\` 转义
     fn read_str(&mut self, first: u8) -> Result<Token> {
         assert_eq!(first as char, '\"');
-        let mut str = Vec::new();
+        let mut value = Vec::new();
+        let mut raw = Vec::new();
+        // 非法转义不立即返回，读到字符串结尾再报错，方便错误恢复
+        let mut invalid = None;
         loop {
+            let start = self.pos();
             let ch = match self.next_byte()? {
                 Some(ch) => ch,
-                None => return Err("expect(\")".into()),
+                None => return lex_err("unterminated string literal"),
             };
             match ch {
                 b'\"' => break,
-                _ => str.push(ch),
+                b'\\' => {
+                    let escape = match self.next_byte()? {
+                        Some(escape) => escape,
+                        None => return lex_err("unterminated string literal"),
+                    };
+                    let byte = match escape {
+                        b'b' => 0x08,
+                        b't' => b'\t',
+                        b'n' => b'\n',
+                        b'f' => 0x0C,
+                        b'r' => b'\r',
+                        b'\"' => b'\"',
+                        b'\\' => b'\\',
+                        _ => {
+                            if invalid.is_none() {
+                                let span = self.span_from(start);
+                                let message = format!("invalid escape \\{}", escape as char);
+                                invalid = Some(Error::spanned(ErrorKind::Lex, span, message));
+                            }
+                            escape
+                        }
+                    };
+                    raw.extend([ch, escape]);
+                    value.push(byte);
+                }
+                _ => {
+                    raw.push(ch);
+                    value.push(ch);
+                    // GBK、Big5的第二个字节可能是 `\\`、`"`，和第一个字节一起读
+                    if let Some(trail) = self.trail_byte(ch)? {
+                        raw.push(trail);
+                        value.push(trail);
+                    }
+                }
+            }
+        }
+        if let Some(e) = invalid {
+            return Err(e);
+        }
+        Ok(Token::String { value, raw })
+    }
+
+    /// GBK、Big5中 `lead` 是双字节字符的第一个字节时，读取第二个字节。
+    /// 第二个字节可能是ASCII，不能单独解释
+    fn trail_byte(&mut self, lead: u8) -> Result<Option<u8>> {
+        let double_byte = matches!(
+            self.decoder.encoding(),
+            SourceEncoding::Gbk | SourceEncoding::Big5
+        );
+        if !double_byte || !(0x81..=0xFE).contains(&lead) {
+            return Ok(None);
+        }
+        match self.peek_byte()? {
+            Some(trail @ 0x40..=0xFE) if trail != 0x7F => {
+                self.next_byte()?;
+                Ok(Some(trail))
             }
+            _ => Ok(None),
         }
-        Ok(Token::String(str))
     }
 
-    ///! 读取数字，整数或浮点数
+    /// 读取数字，整数或浮点数
+    /// 整数支持十进制、八进制(`017`)、十六进制(`0xFF`、`$FF`)，浮点数支持 `.5` 和 `1.`
     fn read_number(&mut self, first: u8) -> Result<Token> {
+        if first == b'$' {
+            let digits = self.read_digits(|ch| ch.is_ascii_alphanumeric())?;
+            return integer_literal(&digits, 16);
+        }
+        if first == b'0'
+            && (self.guess_byte_and_consume(b'x')? || self.guess_byte_and_consume(b'X')?)
+        {
+            let digits = self.read_digits(|ch| ch.is_ascii_alphanumeric())?;
+            return integer_literal(&digits, 16);
+        }
+
         let mut str = String::new();
         str.push(first as char);
-        let mut dot = false;
-        loop {
-            let ch = match self.peek_byte()? {
-                Some(ch) => ch,
-                None => {
-                    break;
-                }
-            };
+        let mut dot = first == b'.';
+        while let Some(ch) = self.peek_byte()? {
             match ch {
                 b'0'..=b'9' => {
                     self.next_byte()?;
@@ -259,61 +802,80 @@ impl<R: Read> Lex<R> {
             }
         }
 
-        return Ok(if dot {
-            Token::Float(str.parse::<f64>()?)
+        if dot {
+            if str == "." {
+                return lex_err("expect digit after '.'");
+            }
+            match str.parse::<f64>() {
+                Ok(value) => Ok(Token::Float(value)),
+                Err(e) => lex_err(e.to_string()),
+            }
+        } else if str.len() > 1 && str.starts_with('0') {
+            integer_literal(&str[1..], 8)
         } else {
-            Token::Integer(str.parse::<i64>()?)
-        });
+            integer_literal(&str, 10)
+        }
+    }
+
+    fn read_digits(&mut self, accept: fn(u8) -> bool) -> Result<String> {
+        let mut str = String::new();
+        while let Some(ch) = self.peek_byte()? {
+            if !accept(ch) {
+                break;
+            }
+            self.next_byte()?;
+            str.push(ch as char);
+        }
+        Ok(str)
     }
 
-    ///! 读取特殊数字，单引号包裹的4位id
+    /// 读取特殊数字，单引号包裹的1位或4位id，按256进制大端序计算。
+    /// 最多读4个字符，遇到换行也结束，缺少右引号时不会吞掉后面的代码
     fn read_snumber(&mut self, first: u8) -> Result<Token> {
         assert_eq!(first as char, '\'');
-        let mut str = Vec::new();
+        let mut text = Vec::new();
         loop {
-            let ch = match self.next_byte()? {
-                Some(ch) => ch,
-                None => return Err("expect(\')".into()),
-            };
-            match ch {
-                b'\'' => break,
-                _ => str.push(ch),
+            match self.peek_byte()? {
+                Some(b'\'') => {
+                    self.next_byte()?;
+                    break;
+                }
+                Some(b'\n' | b'\r') | None => return lex_err("unterminated rawcode literal"),
+                Some(_) if text.len() == 4 => return lex_err("unterminated rawcode literal"),
+                Some(ch) => {
+                    self.next_byte()?;
+                    text.push(ch);
+                }
             }
         }
-        if str.len() != 4 {
-            println!("error str: {str:?}");
-            return Err("invaild single quotes number".into());
+        if text.len() != 1 && text.len() != 4 {
+            return lex_err(format!(
+                "rawcode literal must be 1 or 4 characters: '{}'",
+                String::from_utf8_lossy(&text)
+            ));
         }
-        let p1 = *str.get(0).unwrap() as i64 * 255 * 255 * 255;
-        let p2 = *str.get(1).unwrap() as i64 * 255 * 255;
-        let p3 = *str.get(2).unwrap() as i64 * 255;
-        let p4 = *str.get(3).unwrap() as i64;
-        Ok(Token::Integer(p1 + p2 + p3 + p4))
+        let value = text
+            .iter()
+            .fold(0u32, |value, &ch| (value << 8) | ch as u32);
+        Ok(Token::RawCode { value, text })
     }
 
     fn skip_annotations(&mut self) -> Result<()> {
         let ch = self.next_byte()?.unwrap();
         assert!(ch == b'*' || ch == b'/');
         if ch == b'/' {
-            loop {
-                let ch = match self.next_byte()? {
-                    Some(ch) => ch,
-                    None => break,
-                };
-
+            // 行尾的换行符留给 next_token 处理
+            while let Some(ch) = self.peek_byte()? {
                 match ch {
                     b'\n' | b'\r' => break,
-                    _ => {}
+                    _ => {
+                        self.next_byte()?;
+                    }
                 }
             }
         } else {
             let mut star = false;
-            loop {
-                let ch = match self.next_byte()? {
-                    Some(ch) => ch,
-                    None => break,
-                };
-
+            while let Some(ch) = self.next_byte()? {
                 match ch {
                     b'*' => {
                         star = true;
@@ -331,135 +893,234 @@ impl<R: Read> Lex<R> {
         Ok(())
     }
 
-    ///! 读取一个名字，名字可能是关键字也可能是变量名称
-    fn read_name(&mut self, first: u8) -> Result<Token> {
-        let mut name = String::new();
-        name.push(first as char);
-        loop {
-            let ch = match self.peek_byte()? {
-                Some(ch) => ch,
-                None => break,
-            };
-
-            match ch {
-                b' ' | b'\n' | b'\r' | b'\t' | b',' | b'(' | b')' | b'=' | b'/' | b'*' | b'+'
-                | b'-' | b'[' | b']' => break,
-                _ => {
-                    self.next_byte()?;
-                    name.push(ch as char);
+    /// 读取一个名字，名字可能是关键字也可能是变量名称
+    fn read_name(&mut self, first: u8, start: Pos) -> Result<Token> {
+        // 内存中的输入直接借出名字，否则先复制到缓冲区
+        let borrowed = self.input.consumed(start.num).is_some();
+        self.buffer.clear();
+        if !borrowed {
+            self.buffer.push(first);
+        }
+        // 名字由字母、数字、下划线组成，非ASCII字符按源码编码解码
+        while let Some(ch) = self.peek_byte()? {
+            if !ch.is_ascii_alphanumeric() && ch != b'_' && ch.is_ascii() {
+                break;
+            }
+            self.next_byte()?;
+            if !borrowed {
+                self.buffer.push(ch);
+            }
+            if let Some(trail) = self.trail_byte(ch)? {
+                if !borrowed {
+                    self.buffer.push(trail);
                 }
             }
         }
-        let token = match name.as_str() {
-            "globals" => Token::Globals,
-            "endglobals" => Token::Endglobals,
-            "constant" => Token::Constant,
-            "native" => Token::Native,
-            "array" => Token::Array,
-            "and" => Token::And,
-            "or" => Token::Or,
-            "not" => Token::Not,
-            "type" => Token::Type,
-            "extends" => Token::Extends,
-            "function" => Token::Function,
-            "endfunction" => Token::Endfunction,
-            "nothing" => Token::Nothing,
-            "takes" => Token::Takes,
-            "returns" => Token::Returns,
-            "call" => Token::Call,
-            "set" => Token::Set,
-            "return" => Token::Return,
-            "if" => Token::If,
-            "then" => Token::Then,
-            "endif" => Token::Endif,
-            "elseif" => Token::Elseif,
-            "else" => Token::Else,
-            "loop" => Token::Loop,
-            "endloop" => Token::Endloop,
-            "exitwhen" => Token::Exitwhen,
-            "local" => Token::Local,
-            "true" => Token::True,
-            "false" => Token::False,
-            "null" => Token::Null,
-            _ => Token::Name(name),
+        let name = match self.input.consumed(start.num) {
+            Some(name) => name,
+            None => &self.buffer,
+        };
+        let token = match name {
+            b"globals" => Token::Globals,
+            b"endglobals" => Token::Endglobals,
+            b"constant" => Token::Constant,
+            b"native" => Token::Native,
+            b"array" => Token::Array,
+            b"and" => Token::And,
+            b"or" => Token::Or,
+            b"not" => Token::Not,
+            b"type" => Token::Type,
+            b"extends" => Token::Extends,
+            b"function" => Token::Function,
+            b"endfunction" => Token::Endfunction,
+            b"nothing" => Token::Nothing,
+            b"takes" => Token::Takes,
+            b"returns" => Token::Returns,
+            b"call" => Token::Call,
+            b"set" => Token::Set,
+            b"return" => Token::Return,
+            b"if" => Token::If,
+            b"then" => Token::Then,
+            b"endif" => Token::Endif,
+            b"elseif" => Token::Elseif,
+            b"else" => Token::Else,
+            b"loop" => Token::Loop,
+            b"endloop" => Token::Endloop,
+            b"exitwhen" => Token::Exitwhen,
+            b"local" => Token::Local,
+            b"true" => Token::True,
+            b"false" => Token::False,
+            b"null" => Token::Null,
+            b"debug" => Token::Debug,
+            _ => {
+                let name = match std::str::from_utf8(name) {
+                    Ok(name) if name.is_ascii() => self.names.get(name).cloned(),
+                    _ => None,
+                };
+                let name = match name {
+                    Some(name) => name,
+                    None => self.intern_name(start)?,
+                };
+                Token::Name(name)
+            }
         };
         Ok(token)
     }
 
-    ///! 读取一个token
-    fn next_token(&mut self) -> Result<Token> {
-        let ch = match self.input.next().transpose()? {
-            Some(ch) => ch,
-            None => return Ok(Token::Eos),
+    /// 非ASCII的名字必须能按源码编码完整解码
+    #[cold]
+    fn intern_name(&mut self, start: Pos) -> Result<Arc<str>> {
+        let name = match self.input.consumed(start.num) {
+            Some(name) => self.decoder.decode_exact(name),
+            None => self.decoder.decode_exact(&self.buffer),
+        };
+        let Some(name) = name else {
+            let encoding = self.decoder.encoding();
+            return lex_err(format!("name is not valid {encoding:?} text"));
         };
-        let token = match ch {
-            b' ' | b'\n' | b'\r' | b'\t' => self.next_token()?,
-            b'0'..=b'9' => self.read_number(ch)?,
-            b'a'..=b'z' | b'_' | b'A'..=b'Z' => self.read_name(ch)?,
-            b'\'' => self.read_snumber(ch)?,
-            b'\"' => self.read_str(ch)?,
-            b'+' => Token::Add,
-            b'-' => Token::Sub,
-            b'*' => Token::Mul,
-            b'/' => {
-                if self.guess_byte(b'/')? || self.guess_byte(b'*')? {
-                    self.skip_annotations()?;
-                    self.next()?
-                } else {
-                    Token::Div
+        if let Some(name) = self.names.get(name.as_str()) {
+            return Ok(name.clone());
+        }
+        let name: Arc<str> = name.into();
+        self.names.insert(name.clone());
+        Ok(name)
+    }
+
+    /// 读取一个token，跳过空白和注释
+    fn next_token(&mut self) -> Result<SpannedToken> {
+        loop {
+            let start = self.pos();
+            self.captured.clear();
+            let ch = match self.next_byte()? {
+                Some(ch) => ch,
+                None => return Ok(self.spanned(Token::Eos, start)),
+            };
+            let token = match ch {
+                0xEF if start.num == 0 && self.guess_byte_and_consume(0xBB)? => {
+                    // UTF-8 BOM
+                    if !self.guess_byte_and_consume(0xBF)? {
+                        let res = lex_err("invalid byte order mark");
+                        self.recover(start, res)?
+                    } else {
+                        self.push_trivia(TriviaKind::Whitespace, start);
+                        continue;
+                    }
                 }
-            }
-            b'=' => {
-                if self.guess_byte_and_consume(b'=')? {
-                    Token::Equal
-                } else {
-                    Token::Assign
+                b' ' | b'\t' => {
+                    while self.guess_byte_and_consume(b' ')?
+                        || self.guess_byte_and_consume(b'\t')?
+                    {}
+                    self.push_trivia(TriviaKind::Whitespace, start);
+                    continue;
                 }
-            }
-            b'!' => {
-                if self.guess_byte_and_consume(b'=')? {
-                    Token::NotEq
-                } else {
-                    Token::Not
+                b'\n' | b'\r' => {
+                    if ch == b'\r' {
+                        self.guess_byte_and_consume(b'\n')?;
+                    }
+                    if !self.newlines {
+                        self.push_trivia(TriviaKind::Newline, start);
+                        continue;
+                    }
+                    Token::Newline
                 }
-            }
-            b'<' => {
-                if self.guess_byte_and_consume(b'=')? {
-                    Token::LesEq
-                } else {
-                    Token::Less
+                b'0'..=b'9' | b'$' | b'.' => {
+                    let res = self.read_number(ch);
+                    self.recover(start, res)?
                 }
-            }
-            b'>' => {
-                if self.guess_byte_and_consume(b'=')? {
-                    Token::GreEq
-                } else {
-                    Token::Greater
+                b'a'..=b'z' | b'_' | b'A'..=b'Z' => {
+                    let res = self.read_name(ch, start);
+                    self.recover(start, res)?
                 }
-            }
-            b',' => Token::Comma,
-            b'(' => Token::ParL,
-            b')' => Token::ParR,
-            b'[' => Token::SqurL,
-            b']' => Token::SqurR,
-            _ => return Err(format!("invaild char {}", ch as char).into()),
-        };
-        Ok(token)
+                b'\'' => {
+                    let res = self.read_snumber(ch);
+                    self.recover(start, res)?
+                }
+                b'\"' => {
+                    let res = self.read_str(ch);
+                    self.recover(start, res)?
+                }
+                b'+' => Token::Add,
+                b'-' => Token::Sub,
+                b'*' => Token::Mul,
+                b'/' => {
+                    if self.guess_byte(b'/')? || self.guess_byte(b'*')? {
+                        let kind = if self.guess_byte(b'/')? {
+                            TriviaKind::LineComment
+                        } else {
+                            TriviaKind::BlockComment
+                        };
+                        self.skip_annotations()?;
+                        self.push_trivia(kind, start);
+                        continue;
+                    } else {
+                        Token::Div
+                    }
+                }
+                b'=' => {
+                    if self.guess_byte_and_consume(b'=')? {
+                        Token::Equal
+                    } else {
+                        Token::Assign
+                    }
+                }
+                b'!' => {
+                    if self.guess_byte_and_consume(b'=')? {
+                        Token::NotEq
+                    } else {
+                        Token::Not
+                    }
+                }
+                b'<' => {
+                    if self.guess_byte_and_consume(b'=')? {
+                        Token::LesEq
+                    } else {
+                        Token::Less
+                    }
+                }
+                b'>' => {
+                    if self.guess_byte_and_consume(b'=')? {
+                        Token::GreEq
+                    } else {
+                        Token::Greater
+                    }
+                }
+                b',' => Token::Comma,
+                b'(' => Token::ParL,
+                b')' => Token::ParR,
+                b'[' => Token::SqurL,
+                b']' => Token::SqurR,
+                _ => {
+                    // 跳过连续的非法字符
+                    let mut chars = vec![ch];
+                    while let Some(ch) = self.peek_byte()? {
+                        if is_token_start(ch) {
+                            break;
+                        }
+                        self.next_byte()?;
+                        chars.push(ch);
+                    }
+                    let message = format!("invaild char {}", self.decoder.decode(&chars));
+                    self.recover(start, lex_err(message))?
+                }
+            };
+            return Ok(self.spanned(token, start));
+        }
     }
 }
 
 #[test]
+#[allow(clippy::approx_constant)]
 fn test_simple_token() -> Result<()> {
     use std::io::Cursor;
 
     let n = "world hello 3.14 4444 function SetUnitName";
     let mut lex = Lex::new(Cursor::new(n));
-    assert_eq!(lex.next()?, Token::Name("world".to_string()));
-    assert_eq!(lex.next()?, Token::Name("hello".to_string()));
+    assert_eq!(lex.next()?, Token::Name("world".into()));
+    assert_eq!(lex.next()?, Token::Name("hello".into()));
     assert_eq!(lex.next()?, Token::Float(3.14));
     assert_eq!(lex.next()?, Token::Integer(4444));
     assert_eq!(lex.next()?, Token::Function);
-    assert_eq!(lex.next()?, Token::Name("SetUnitName".to_string()));
+    assert_eq!(lex.next()?, Token::Name("SetUnitName".into()));
     Ok(())
 }
 
@@ -467,10 +1128,40 @@ fn test_simple_token() -> Result<()> {
 fn test_single_quotes_number() -> Result<()> {
     use std::io::Cursor;
 
-    let n = "'1234' '5678'";
+    let n = "'1234' '5678' 'hfoo' 'A'";
     let mut lex = Lex::new(Cursor::new(n));
-    assert_eq!(lex.next()?, Token::Integer(815751682));
-    assert_eq!(lex.next()?, Token::Integer(882338306));
+    assert_eq!(
+        lex.next()?,
+        Token::RawCode {
+            value: 825373492,
+            text: b"1234".to_vec()
+        }
+    );
+    assert_eq!(
+        lex.next()?,
+        Token::RawCode {
+            value: 892745528,
+            text: b"5678".to_vec()
+        }
+    );
+    assert_eq!(
+        lex.next()?,
+        Token::RawCode {
+            value: 0x68666F6F,
+            text: b"hfoo".to_vec()
+        }
+    );
+    assert_eq!(
+        lex.next()?,
+        Token::RawCode {
+            value: 65,
+            text: b"A".to_vec()
+        }
+    );
+
+    for n in ["''", "'ab'", "'abcde'", "'abcd"] {
+        assert!(Lex::new(Cursor::new(n)).next().is_err(), "{n}");
+    }
     Ok(())
 }
 
@@ -481,12 +1172,21 @@ fn test_fn() -> Result<()> {
     let n = "function SetUnitName takes nothing returns nothing \n endfunction";
     let mut lex = Lex::new(Cursor::new(n));
     assert_eq!(lex.next()?, Token::Function);
-    assert_eq!(lex.next()?, Token::Name("SetUnitName".to_string()));
+    assert_eq!(lex.next()?, Token::Name("SetUnitName".into()));
     assert_eq!(lex.next()?, Token::Takes);
     assert_eq!(lex.next()?, Token::Nothing);
     assert_eq!(lex.next()?, Token::Returns);
     assert_eq!(lex.next()?, Token::Nothing);
     assert_eq!(lex.next()?, Token::Endfunction);
+
+    let n = "debug call Foo() debugger";
+    let mut lex = Lex::new(Cursor::new(n));
+    assert_eq!(lex.next()?, Token::Debug);
+    assert_eq!(lex.next()?, Token::Call);
+    assert_eq!(lex.next()?, Token::Name("Foo".into()));
+    assert_eq!(lex.next()?, Token::ParL);
+    assert_eq!(lex.next()?, Token::ParR);
+    assert_eq!(lex.next()?, Token::Name("debugger".into()));
     Ok(())
 }
 
@@ -507,7 +1207,7 @@ fn test_line_annotation() -> Result<()> {
     let n = "//function SetUnitName takes nothing returns nothing\nfunction SetUnitName takes nothing returns nothing \n endfunction ";
     let mut lex = Lex::new(Cursor::new(n));
     assert_eq!(lex.next()?, Token::Function);
-    assert_eq!(lex.next()?, Token::Name("SetUnitName".to_string()));
+    assert_eq!(lex.next()?, Token::Name("SetUnitName".into()));
     assert_eq!(lex.next()?, Token::Takes);
     assert_eq!(lex.next()?, Token::Nothing);
     assert_eq!(lex.next()?, Token::Returns);
@@ -522,11 +1222,75 @@ fn test_str() -> Result<()> {
 
     let n = "\"12345\" \"\"";
     let mut lex = Lex::new(Cursor::new(n));
-    assert_eq!(lex.next()?, Token::String("12345".as_bytes().to_vec()));
-    assert_eq!(lex.next()?, Token::String(vec![]));
+    assert_eq!(
+        lex.next()?,
+        Token::String {
+            value: "12345".as_bytes().to_vec(),
+            raw: "12345".as_bytes().to_vec(),
+        }
+    );
+    assert_eq!(
+        lex.next()?,
+        Token::String {
+            value: vec![],
+            raw: vec![]
+        }
+    );
+    Ok(())
+}
+
+#[test]
+fn test_str_escape() -> Result<()> {
+    use std::io::Cursor;
+
+    let n = r#""say \"hi\"" "a\tb\nc\\" "\b\f\r""#;
+    let mut lex = Lex::new(Cursor::new(n));
+    assert_eq!(
+        lex.next()?,
+        Token::String {
+            value: br#"say "hi""#.to_vec(),
+            raw: br#"say \"hi\""#.to_vec(),
+        }
+    );
+    assert_eq!(
+        lex.next()?,
+        Token::String {
+            value: b"a\tb\nc\\".to_vec(),
+            raw: br"a\tb\nc\\".to_vec(),
+        }
+    );
+    assert_eq!(
+        lex.next()?,
+        Token::String {
+            value: vec![0x08, 0x0C, b'\r'],
+            raw: br"\b\f\r".to_vec(),
+        }
+    );
+    assert_eq!(lex.next()?, Token::Eos);
     Ok(())
 }
 
+#[test]
+fn test_str_error() {
+    use std::io::Cursor;
+
+    let n = r#""abc\q""#;
+    let err = Lex::new(Cursor::new(n)).next().unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::Lex);
+    let span = err.span().unwrap();
+    assert_eq!((span.start, span.end), (4, 6));
+
+    let n = "set s = \"abc";
+    let mut lex = Lex::new(Cursor::new(n));
+    lex.next().unwrap();
+    lex.next().unwrap();
+    lex.next().unwrap();
+    let err = lex.next().unwrap_err();
+    assert_eq!(err.message(), "unterminated string literal");
+    let span = err.span().unwrap();
+    assert_eq!((span.start, span.end), (8, 12));
+}
+
 #[test]
 fn test_number_and_char() -> Result<()> {
     use std::io::Cursor;
@@ -544,3 +1308,481 @@ fn test_number_and_char() -> Result<()> {
     assert_eq!(lex.next()?, Token::Eos);
     Ok(())
 }
+
+#[test]
+fn test_integer_literal() -> Result<()> {
+    use std::io::Cursor;
+
+    let n = "0xFF $ff 0X7fffffff 017 0 10 4294967295 0xFFFFFFFF .5 1. 2.25";
+    let mut lex = Lex::new(Cursor::new(n));
+    assert_eq!(lex.next()?, Token::Integer(255));
+    assert_eq!(lex.next()?, Token::Integer(255));
+    assert_eq!(lex.next()?, Token::Integer(i32::MAX as i64));
+    assert_eq!(lex.next()?, Token::Integer(15));
+    assert_eq!(lex.next()?, Token::Integer(0));
+    assert_eq!(lex.next()?, Token::Integer(10));
+    assert_eq!(lex.next()?, Token::Integer(u32::MAX as i64));
+    assert_eq!(lex.next()?, Token::Integer(u32::MAX as i64));
+    assert_eq!(lex.next()?, Token::Float(0.5));
+    assert_eq!(lex.next()?, Token::Float(1.0));
+    assert_eq!(lex.next()?, Token::Float(2.25));
+    assert_eq!(lex.next()?, Token::Eos);
+    Ok(())
+}
+
+#[test]
+fn test_invalid_integer_literal() {
+    use std::io::Cursor;
+
+    for n in ["4294967296", "0x100000000", "$", "0x", "019", "0xFG", "."] {
+        let mut lex = Lex::new(Cursor::new(n));
+        let err = lex.next().expect_err(n);
+        assert_eq!(err.kind(), ErrorKind::Lex, "{n}");
+        let span = err.span().expect("lex error has location");
+        assert_eq!(span.start, 0, "{n}");
+    }
+}
+
+#[test]
+fn test_newline() -> Result<()> {
+    use std::io::Cursor;
+
+    let n = "set a = 1 // one\r\n\n call F()\r";
+    let mut lex = Lex::new(Cursor::new(n)).with_newlines(true);
+    assert_eq!(lex.next()?, Token::Set);
+    assert_eq!(lex.next()?, Token::Name("a".into()));
+    assert_eq!(lex.next()?, Token::Assign);
+    assert_eq!(lex.next()?, Token::Integer(1));
+    let newline = lex.next_spanned()?;
+    assert_eq!(newline.token, Token::Newline);
+    assert_eq!(&n[newline.span.start..newline.span.end], "\r\n");
+    assert_eq!(lex.next()?, Token::Newline);
+    assert_eq!(lex.next()?, Token::Call);
+    assert_eq!(lex.next()?, Token::Name("F".into()));
+    assert_eq!(lex.next()?, Token::ParL);
+    assert_eq!(lex.next()?, Token::ParR);
+    assert_eq!(lex.next()?, Token::Newline);
+    assert_eq!(lex.next()?, Token::Eos);
+
+    let mut lex = Lex::new(Cursor::new(n));
+    assert_eq!(lex.next()?, Token::Set);
+    for _ in 0..3 {
+        lex.next()?;
+    }
+    assert_eq!(lex.next()?, Token::Call);
+    Ok(())
+}
+
+#[test]
+fn test_trivia() -> Result<()> {
+    use std::io::Cursor;
+
+    let n = "  set a = 1 // one\r\n/* two */\tcall F()\n";
+    let mut lex = Lex::new(Cursor::new(n)).with_trivia(true);
+    let set = lex.next_spanned()?;
+    assert_eq!(set.text, b"set");
+    assert_eq!(set.trivia.len(), 1);
+    assert_eq!(set.trivia[0].kind, TriviaKind::Whitespace);
+    assert_eq!(set.trivia[0].text, b"  ");
+    for _ in 0..3 {
+        lex.next()?;
+    }
+    let call = lex.next_spanned()?;
+    let kinds: Vec<_> = call.trivia.iter().map(|trivia| trivia.kind).collect();
+    assert_eq!(
+        kinds,
+        vec![
+            TriviaKind::Whitespace,
+            TriviaKind::LineComment,
+            TriviaKind::Newline,
+            TriviaKind::BlockComment,
+            TriviaKind::Whitespace,
+        ]
+    );
+    assert_eq!(call.trivia[1].text, b"// one");
+    assert_eq!(call.trivia[2].text, b"\r\n");
+    assert_eq!(call.trivia[3].text, b"/* two */");
+    assert_eq!(
+        &n[call.trivia[3].span.start..call.trivia[3].span.end],
+        "/* two */"
+    );
+    Ok(())
+}
+
+#[test]
+fn test_lossless_round_trip() -> Result<()> {
+    use std::io::Cursor;
+
+    let n = include_str!("../tests/common.j");
+    for newlines in [false, true] {
+        let mut lex = Lex::new(Cursor::new(n))
+            .with_trivia(true)
+            .with_newlines(newlines);
+        let mut tokens = vec![];
+        loop {
+            let token = lex.next_spanned()?;
+            let eos = token.token == Token::Eos;
+            tokens.push(token);
+            if eos {
+                break;
+            }
+        }
+        assert_eq!(source_text(&tokens), n.as_bytes());
+    }
+    Ok(())
+}
+
+#[test]
+fn test_recovery() -> Result<()> {
+    use std::io::Cursor;
+
+    let n = "set a = 1 #@ b\nset c = 0x 'ab' \"x\\q\" d\nset e = \"abc";
+    let mut lex = Lex::new(Cursor::new(n)).with_recovery(true);
+    let mut tokens = vec![];
+    loop {
+        let token = lex.next()?;
+        if token == Token::Eos {
+            break;
+        }
+        tokens.push(token);
+    }
+    assert_eq!(
+        tokens,
+        vec![
+            Token::Set,
+            Token::Name("a".into()),
+            Token::Assign,
+            Token::Integer(1),
+            Token::Error,
+            Token::Name("b".into()),
+            Token::Set,
+            Token::Name("c".into()),
+            Token::Assign,
+            Token::Error,
+            Token::Error,
+            Token::Error,
+            Token::Name("d".into()),
+            Token::Set,
+            Token::Name("e".into()),
+            Token::Assign,
+            Token::Error,
+        ]
+    );
+
+    let errors: Vec<_> = lex
+        .errors()
+        .iter()
+        .map(|e| {
+            let span = e.span().unwrap();
+            (span.start_line, span.start_col, e.message())
+        })
+        .collect();
+    assert_eq!(
+        errors,
+        vec![
+            (1, 11, "invaild char #@"),
+            (2, 9, "missing digits in integer literal"),
+            (2, 12, "rawcode literal must be 1 or 4 characters: 'ab'"),
+            (2, 19, "invalid escape \\q"),
+            (3, 9, "unterminated string literal"),
+        ]
+    );
+    Ok(())
+}
+
+#[test]
+fn test_encoding() -> Result<()> {
+    use std::io::Cursor;
+
+    let n = b"\xEF\xBB\xBFcall A\xC4\xE3(\"\xBA\xC3\") \xA3\xA1";
+    let mut lex = Lex::new(Cursor::new(n)).with_encoding(SourceEncoding::Gbk);
+    assert_eq!(lex.next()?, Token::Call);
+    assert_eq!(lex.next()?, Token::Name("A你".into()));
+    assert_eq!(lex.next()?, Token::ParL);
+    let Token::String { value, .. } = lex.next()? else {
+        panic!("expect string");
+    };
+    assert_eq!(value, vec![0xBA, 0xC3]);
+    assert_eq!(lex.decode(&value), "好");
+    assert_eq!(lex.next()?, Token::ParR);
+    let err = lex.next().unwrap_err();
+    assert_eq!(err.message(), "invaild char ！");
+    Ok(())
+}
+
+#[test]
+fn test_slice_lex() -> Result<()> {
+    use std::io::Cursor;
+
+    let n = concat!(
+        include_str!("../tests/common.j"),
+        include_str!("../tests/blizzard.j")
+    );
+    let mut read = Lex::new(Cursor::new(n)).with_trivia(true);
+    let mut slice = Lex::from_slice(n.as_bytes(), FileId::default()).with_trivia(true);
+    loop {
+        let token = read.next_spanned()?;
+        assert_eq!(slice.next_spanned()?, token);
+        if token.token == Token::Eos {
+            break;
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn test_interned_name() -> Result<()> {
+    let n = b"call Foo(Foo, Bar) call Foo()";
+    let mut lex = Lex::from_slice(n, FileId::default());
+    let mut names = vec![];
+    loop {
+        match lex.next()? {
+            Token::Name(name) => names.push(name),
+            Token::Eos => break,
+            _ => {}
+        }
+    }
+    assert_eq!(names.len(), 4);
+    assert!(Arc::ptr_eq(&names[0], &names[1]));
+    assert!(Arc::ptr_eq(&names[0], &names[3]));
+    assert_eq!(&*names[2], "Bar");
+    Ok(())
+}
+
+#[test]
+fn test_token_span() -> Result<()> {
+    use std::io::Cursor;
+
+    let n = "function Main\n  // comment\n  set abc = 10";
+    let mut lex = Lex::new(Cursor::new(n));
+    let function = lex.next_spanned()?;
+    assert_eq!(function.token, Token::Function);
+    assert_eq!((function.span.start, function.span.end), (0, 8));
+    assert_eq!((function.span.start_line, function.span.start_col), (1, 1));
+    assert_eq!((function.span.end_line, function.span.end_col), (1, 9));
+
+    // peek 不会改变已消费token的位置
+    assert_eq!(lex.peek_spanned()?.span.start, 9);
+    assert_eq!(lex.span().start, 0);
+
+    let main = lex.next_spanned()?;
+    assert_eq!((main.span.start, main.span.end), (9, 13));
+
+    let set = lex.next_spanned()?;
+    assert_eq!(set.token, Token::Set);
+    assert_eq!((set.span.start_line, set.span.start_col), (3, 3));
+
+    lex.next()?;
+    lex.next()?;
+    let ten = lex.next_spanned()?;
+    assert_eq!(ten.token, Token::Integer(10));
+    assert_eq!(&n[ten.span.start..ten.span.end], "10");
+    assert_eq!((ten.span.end_line, ten.span.end_col), (3, 15));
+    assert_eq!(lex.next()?, Token::Eos);
+    Ok(())
+}
+
+#[test]
+fn test_relex() -> Result<()> {
+    fn lex_all(n: &[u8], trivia: bool, newlines: bool) -> Result<Vec<SpannedToken>> {
+        let mut lex = Lex::from_slice(n, FileId::default())
+            .with_trivia(trivia)
+            .with_newlines(newlines)
+            .with_recovery(true);
+        let mut tokens = vec![];
+        loop {
+            let token = lex.next_spanned()?;
+            let eos = token.token == Token::Eos;
+            tokens.push(token);
+            if eos {
+                return Ok(tokens);
+            }
+        }
+    }
+
+    let source = &include_bytes!("../tests/common.j")[..16384];
+    let texts = [
+        "", "x", " ", "\n", "\r\n", "/*", "*/", "//", "\"", "'", "1.5", "0x", "function", "$",
+    ];
+    let mut seed = 7u64;
+    let mut random = |n: usize| {
+        seed = seed
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        (seed >> 33) as usize % n
+    };
+    for trivia in [false, true] {
+        for newlines in [false, true] {
+            let mut text = source.to_vec();
+            let mut tokens = lex_all(&text, trivia, newlines)?;
+            for _ in 0..100 {
+                let start = random(text.len() + 1);
+                let end = (start + random(8)).min(text.len());
+                let edit = TextEdit::new(start..end, texts[random(texts.len())]);
+                let edited = edit.apply(&text);
+                let lex = Lex::from_slice(&edited, FileId::default())
+                    .with_trivia(trivia)
+                    .with_newlines(newlines)
+                    .with_recovery(true);
+                let relexed = lex.relex(&tokens, &edit)?;
+                assert_eq!(relexed, lex_all(&edited, trivia, newlines)?, "{edit:?}");
+                if trivia {
+                    assert_eq!(source_text(&relexed), edited);
+                }
+                text = edited;
+                tokens = relexed;
+            }
+        }
+    }
+
+    // 编辑点之后的token直接复用
+    let n = b"set a = 1\ncall Foo(bar)";
+    let tokens = lex_all(n, false, false)?;
+    let edit = TextEdit::new(8..9, "22");
+    let edited = edit.apply(n);
+    let relexed = Lex::from_slice(&edited, FileId::default()).relex(&tokens, &edit)?;
+    assert_eq!(relexed[3].token, Token::Integer(22));
+    let (Token::Name(old), Token::Name(new)) = (&tokens[7].token, &relexed[7].token) else {
+        panic!("expect name");
+    };
+    assert!(Arc::ptr_eq(old, new));
+    assert_eq!((relexed[7].span.start, relexed[7].span.start_col), (20, 10));
+
+    let edit = TextEdit::new(8..100, "");
+    assert!(Lex::from_slice(n, FileId::default())
+        .relex(&tokens, &edit)
+        .is_err());
+    Ok(())
+}
+
+#[test]
+fn test_unterminated_rawcode() -> Result<()> {
+    let n = b"set a = 'abc\nset b = 1\nset c = 'abcd + 2";
+    let mut lex = Lex::from_slice(n, FileId::default()).with_recovery(true);
+    let mut tokens = vec![];
+    loop {
+        let token = lex.next()?;
+        if token == Token::Eos {
+            break;
+        }
+        tokens.push(token);
+    }
+    assert_eq!(
+        tokens,
+        [
+            Token::Set,
+            Token::Name("a".into()),
+            Token::Assign,
+            Token::Error,
+            Token::Set,
+            Token::Name("b".into()),
+            Token::Assign,
+            Token::Integer(1),
+            Token::Set,
+            Token::Name("c".into()),
+            Token::Assign,
+            Token::Error,
+            Token::Add,
+            Token::Integer(2),
+        ]
+    );
+    let errors: Vec<_> = lex
+        .errors()
+        .iter()
+        .map(|e| (e.span().unwrap().start_line, e.message()))
+        .collect();
+    assert_eq!(
+        errors,
+        [
+            (1, "unterminated rawcode literal"),
+            (3, "unterminated rawcode literal"),
+        ]
+    );
+    Ok(())
+}
+
+#[test]
+fn test_name_chars() -> Result<()> {
+    for (n, op) in [
+        ("a>b", Token::Greater),
+        ("a<=b", Token::LesEq),
+        ("a!=b", Token::NotEq),
+    ] {
+        let mut lex = Lex::from_slice(n.as_bytes(), FileId::default());
+        assert_eq!(lex.next()?, Token::Name("a".into()), "{n}");
+        assert_eq!(lex.next()?, op, "{n}");
+        assert_eq!(lex.next()?, Token::Name("b".into()), "{n}");
+        assert_eq!(lex.next()?, Token::Eos, "{n}");
+    }
+
+    let mut lex = Lex::from_slice(b"foo#bar", FileId::default()).with_recovery(true);
+    assert_eq!(lex.next()?, Token::Name("foo".into()));
+    assert_eq!(lex.next()?, Token::Error);
+    assert_eq!(lex.next()?, Token::Name("bar".into()));
+    assert_eq!(lex.errors()[0].message(), "invaild char #");
+
+    // 名字中不能按源码编码解码的字节
+    for encoding in [SourceEncoding::Utf8, SourceEncoding::Gbk] {
+        let mut lex = Lex::from_slice(b"set a\xFFb = 1", FileId::default())
+            .with_encoding(encoding)
+            .with_recovery(true);
+        assert_eq!(lex.next()?, Token::Set);
+        assert_eq!(lex.next()?, Token::Error);
+        assert_eq!(lex.next()?, Token::Assign);
+        let e = &lex.errors()[0];
+        assert_eq!(e.kind(), ErrorKind::Lex);
+        assert_eq!(e.span().unwrap().start_col, 5);
+        assert_eq!(e.message(), format!("name is not valid {encoding:?} text"));
+    }
+    Ok(())
+}
+
+#[test]
+fn test_big5_trail_byte() -> Result<()> {
+    // 功 的Big5编码是 A5 5C，第二个字节是反斜杠
+    let n = b"set a\xA5\x5C = \"\xA5\x5C\xAF\xE0\" + \"\xA5\x5C\"";
+    let mut lex = Lex::from_slice(n, FileId::default()).with_encoding(SourceEncoding::Big5);
+    assert_eq!(lex.next()?, Token::Set);
+    assert_eq!(lex.next()?, Token::Name("a功".into()));
+    assert_eq!(lex.next()?, Token::Assign);
+    let Token::String { value, .. } = lex.next()? else {
+        panic!("expect string");
+    };
+    assert_eq!(lex.decode(&value), "功能");
+    assert_eq!(lex.next()?, Token::Add);
+    let Token::String { value, .. } = lex.next()? else {
+        panic!("expect string");
+    };
+    assert_eq!(value, [0xA5, 0x5C]);
+    assert_eq!(lex.next()?, Token::Eos);
+
+    // Auto在分词前确定编码，第一个字符串就能正确分词
+    let mut lex = Lex::from_slice(n, FileId::default()).with_encoding(SourceEncoding::Auto);
+    assert_eq!(lex.encoding(), SourceEncoding::Big5);
+    let tokens: Vec<_> = std::iter::from_fn(|| match lex.next() {
+        Ok(Token::Eos) => None,
+        res => Some(res),
+    })
+    .collect::<Result<_>>()?;
+    assert_eq!(tokens.len(), 6);
+    assert_eq!(tokens[1], Token::Name("a功".into()));
+
+    // GBK中 95 5C 是一个字
+    let n = b"set s = \"\x95\x5C\" + \"x\"";
+    let mut lex = Lex::from_slice(n, FileId::default()).with_encoding(SourceEncoding::Auto);
+    assert_eq!(lex.encoding(), SourceEncoding::Gbk);
+    for token in [Token::Set, Token::Name("s".into()), Token::Assign] {
+        assert_eq!(lex.next()?, token);
+    }
+    let Token::String { value, .. } = lex.next()? else {
+        panic!("expect string");
+    };
+    assert_eq!(value, [0x95, 0x5C]);
+    assert_eq!(lex.next()?, Token::Add);
+    let Token::String { value, .. } = lex.next()? else {
+        panic!("expect string");
+    };
+    assert_eq!(value, b"x");
+    assert_eq!(lex.next()?, Token::Eos);
+    Ok(())
+}
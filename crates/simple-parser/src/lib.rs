@@ -1,10 +1,25 @@
+pub mod ast;
+mod codegen;
+mod diagnostic;
+mod encoding;
 mod error;
 mod lex;
 mod parse;
+mod program;
+mod span;
+mod types;
+mod unit;
 
+pub use codegen::*;
+pub use diagnostic::*;
+pub use encoding::*;
 pub use error::*;
 pub use lex::*;
 pub use parse::*;
+pub use program::*;
+pub use span::*;
+pub use types::*;
+pub use unit::*;
 
 pub fn add(left: u64, right: u64) -> u64 {
     left + right
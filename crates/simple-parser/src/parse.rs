@@ -1,97 +1,101 @@
+use crate::ast::{
+    BinaryOp, Call, Decl, Expr, ExprKind, File, FunctionDecl, FunctionSig, Globals, Ident,
+    IfBranch, IfStmt, NativeDecl, Param, Stmt, StmtKind, TypeDecl, UnaryOp, VarDecl, VarKind,
+};
+use crate::Codegen;
+use crate::Error;
+use crate::ErrorKind;
+use crate::FileId;
 use crate::Lex;
+use crate::LiteralString;
+use crate::Program;
+use crate::ReadSource;
 use crate::Result;
+use crate::SliceRead;
+use crate::Source;
+use crate::SourceEncoding;
+use crate::Span;
+use crate::SpannedToken;
 use crate::Token;
-use garygo_jass_common::Bytecode;
-use garygo_jass_common::BytecodeValueType;
-use garygo_jass_common::Reg;
-use garygo_jass_common::SymbolId;
-use std::collections::HashMap;
-use std::fmt::Debug;
 use std::io::Read;
 
-#[derive(Clone, PartialEq)]
-pub struct ScriptType {
-    name: String,
-    extends: String,
-    base: BytecodeValueType,
-    array: bool,
+/// 消费token时的回调，见 `Parse::with_trace`
+type Trace = Box<dyn FnMut(&SpannedToken)>;
+
+/// 把token流解析成语法树，`file` 再交给 `Codegen` 生成字节码
+pub struct Parse<S: Source> {
+    lex: Lex<S>,
+    newlines: bool,
+    recovery: bool,
+    errors: Vec<Error>,
+    codegen: Codegen,
+    trace: Option<Trace>,
 }
 
-impl Debug for ScriptType {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        if self.extends.len() > 0 {
-            write!(
-                f,
-                "ScriptType({} extends {})",
-                self.name.as_str(),
-                self.extends.as_str()
-            )
-        } else {
-            write!(f, "ScriptType({})", self.name.as_str())
+impl<S: Source> Parse<S> {
+    fn new(lex: Lex<S>) -> Parse<S> {
+        Parse {
+            lex,
+            newlines: false,
+            recovery: false,
+            errors: vec![],
+            codegen: Codegen::new(),
+            trace: None,
         }
     }
-}
 
-#[derive(Clone)]
-pub struct FunctionArg {
-    name: String,
-    script_type: ScriptType,
-    #[allow(dead_code)]
-    idx: u8,
-}
+    /// 源码编码，默认UTF-8
+    pub fn with_encoding(mut self, encoding: SourceEncoding) -> Self {
+        self.lex = self.lex.with_encoding(encoding);
+        self
+    }
 
-#[derive(Clone)]
-pub struct Function {
-    name: String,
-    args: Vec<FunctionArg>,
-    ret: Option<ScriptType>,
-}
+    /// 严格按行解析，每行只允许一条语句或声明，和官方编译器、pJass一致
+    pub fn with_newlines(mut self, newlines: bool) -> Self {
+        self.lex = self.lex.with_newlines(newlines);
+        self.newlines = newlines;
+        self
+    }
 
-impl Debug for Function {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "function {}(", self.name)?;
-        for FunctionArg {
-            name, script_type, ..
-        } in &self.args
-        {
-            write!(f, " {} {name}", script_type.name)?;
-        }
-        write!(f, ") => {:?}", self.ret)?;
-        Ok(())
+    /// debug构建保留 `debug` 开头的语句，release构建（默认）只做检查不生成字节码
+    pub fn with_debug(mut self, debug: bool) -> Self {
+        self.codegen = self.codegen.with_debug(debug);
+        self
     }
-}
 
-pub struct Parse<R: Read> {
-    bytecodes: Vec<Bytecode>,
-    symbol_table: Vec<String>,
-    types: HashMap<usize, ScriptType>,
-    functions: HashMap<usize, Function>,
-    natives: HashMap<usize, Function>,
-    var_type: HashMap<usize, ScriptType>,
-    strings: Vec<String>,
-    string_index_map: HashMap<String, u32>,
-    loop_label_num: Vec<u32>,
-    label_num: u32,
-    lex: Lex<R>,
-    reg: u8,
-}
+    /// 出错后跳到下一条语句或声明继续解析，收集所有错误，词法错误也一并收集
+    pub fn with_recovery(mut self, recovery: bool) -> Self {
+        self.lex = self.lex.with_recovery(recovery);
+        self.recovery = recovery;
+        self
+    }
 
-impl<R: Read> Parse<R> {
-    fn new(r: R) -> Parse<R> {
-        Parse {
-            bytecodes: vec![],
-            lex: Lex::new(r),
-            symbol_table: vec![],
-            types: HashMap::new(),
-            functions: HashMap::new(),
-            natives: HashMap::new(),
-            var_type: HashMap::new(),
-            strings: vec![],
-            string_index_map: HashMap::new(),
-            loop_label_num: vec![],
-            label_num: 0,
-            reg: 0x00,
-        }
+    /// 每消费一个token调用一次，用于调试parser，默认不输出任何内容
+    pub fn with_trace(mut self, trace: impl FnMut(&SpannedToken) + 'static) -> Self {
+        self.trace = Some(Box::new(trace));
+        self
+    }
+
+    /// 错误恢复模式下收集到的语法和词法错误
+    pub fn errors(&self) -> &[Error] {
+        &self.errors
+    }
+
+    pub fn take_errors(&mut self) -> Vec<Error> {
+        std::mem::take(&mut self.errors)
+    }
+
+    pub fn codegen(&self) -> &Codegen {
+        &self.codegen
+    }
+
+    /// 编译结果，需要先调用 `file`
+    pub fn program(&self) -> &Program {
+        self.codegen.program()
+    }
+
+    pub fn into_program(self) -> Program {
+        self.codegen.into_program()
     }
 
     fn peek(&mut self) -> Result<&Token> {
@@ -99,9 +103,24 @@ impl<R: Read> Parse<R> {
     }
 
     fn next(&mut self) -> Result<Token> {
-        let peek = self.lex.peek()?;
-        println!("{:?}", peek.clone());
-        self.lex.next()
+        Ok(self.next_spanned()?.token)
+    }
+
+    fn next_spanned(&mut self) -> Result<SpannedToken> {
+        let token = self.lex.next_spanned()?;
+        if let Some(trace) = &mut self.trace {
+            trace(&token);
+        }
+        Ok(token)
+    }
+
+    fn peek_span(&mut self) -> Result<Span> {
+        Ok(self.lex.peek_spanned()?.span)
+    }
+
+    /// 最近一次消费的token的位置
+    fn span(&self) -> Span {
+        self.lex.span()
     }
 
     fn guess(&mut self, guess: &Token) -> Result<bool> {
@@ -119,566 +138,236 @@ impl<R: Read> Parse<R> {
 
     fn expect_consume(&mut self, expect: &Token) -> Result<()> {
         if !self.guess(expect)? {
-            return Err(format!("unexpect token: {expect:?}").into());
+            let SpannedToken { token, span, .. } = self.lex.peek_spanned()?;
+            return err(*span, format!("expect {expect:?}, but {token:?}"));
         }
         self.next()?;
         Ok(())
     }
 
-    fn symbol_index(&mut self, symbol: &str) -> Result<usize> {
-        let table = &mut self.symbol_table;
-        let position = table
-            .iter()
-            .position(|v| v.as_str() == symbol)
-            .unwrap_or_else(|| {
-                table.push(symbol.to_string());
-                table.len() - 1
-            });
-        Ok(position)
-    }
-
-    fn get_symbol_index(&self, symbol: &str) -> Option<usize> {
-        let table = &self.symbol_table;
-        table.iter().position(|v| v.as_str() == symbol)
+    fn skip_newlines(&mut self) -> Result<()> {
+        while self.guess(&Token::Newline)? {
+            self.next()?;
+        }
+        Ok(())
     }
 
-    fn typeinfo(&self, symbol: &str) -> Option<&ScriptType> {
-        let idx = self.get_symbol_index(symbol)?;
-        let script_type = self.types.get(&idx)?;
-        Some(script_type)
+    /// 语句或声明之后必须换行，宽松模式下不检查
+    fn end_of_line(&mut self) -> Result<()> {
+        if !self.newlines {
+            return Ok(());
+        }
+        let SpannedToken { token, span, .. } = self.lex.peek_spanned()?;
+        match token {
+            Token::Newline => self.skip_newlines(),
+            Token::Eos => Ok(()),
+            _ => err(*span, format!("expect end of line, but {token:?}")),
+        }
     }
 
-    /// 依赖文法保证安全性
-    fn set_var_type(&mut self, symbol: SymbolId, script_type: ScriptType) {
-        let idx = u32::from(symbol) as usize;
-        self.var_type.insert(idx, script_type);
+    fn unexpected<T>(&mut self, expect: &str) -> Result<T> {
+        let SpannedToken { token, span, .. } = self.lex.peek_spanned()?;
+        err(*span, format!("expect {expect}, but {token:?}"))
     }
 
-    fn get_var_type(&self, symbol: SymbolId) -> Result<&ScriptType> {
-        let idx = u32::from(symbol) as usize;
-        let t = self.var_type.get(&idx);
-        match t {
-            Some(x) => Ok(x),
-            None => Err("not found var defined".into()),
+    /// 错误恢复模式下记录错误，否则直接返回错误
+    fn report(&mut self, e: Error) -> Result<()> {
+        if !self.recovery {
+            return Err(e);
         }
-    }
-
-    fn next_reg(&mut self) -> u8 {
-        if self.reg == u8::MAX {
-            self.reg = 1
-        } else {
-            self.reg += 1;
+        let Some(span) = e.span() else {
+            return Err(e);
+        };
+        // 同一位置只报告一次，词法错误已经由lexer记录
+        let reported = |errors: &[Error]| {
+            errors
+                .iter()
+                .any(|e| e.span().is_some_and(|s| s.start == span.start))
+        };
+        if !reported(&self.errors) && !reported(self.lex.errors()) {
+            self.errors.push(e);
         }
-        self.reg
+        Ok(())
     }
 
-    fn add_literal_string(&mut self, str: Vec<u8>) -> Result<u32> {
-        let str = String::from_utf8(str)?;
-        if let Some(x) = self.string_index_map.get(str.as_str()) {
-            return Ok(*x);
+    /// 跳过token直到 `from` 之后第一个满足 `stop` 的token，`stop` 的第二个参数表示token是否在行首
+    fn synchronize(&mut self, from: usize, stop: fn(&Token, bool) -> bool) -> Result<()> {
+        loop {
+            let last_line = self.span().start_line;
+            let SpannedToken { token, span, .. } = self.lex.peek_spanned()?;
+            if *token == Token::Eos {
+                return Ok(());
+            }
+            if span.start >= from && stop(token, span.start_line > last_line) {
+                return Ok(());
+            }
+            self.next()?;
         }
-        let idx = self.strings.len() as u32;
-        self.strings.push(str.clone());
-        self.string_index_map.insert(str, idx);
-        Ok(idx)
-    }
-
-    fn next_label(&mut self) -> u32 {
-        self.label_num += 1;
-        self.label_num
     }
+}
 
-    fn push_loop(&mut self) -> u32 {
-        let label = self.next_label();
-        self.loop_label_num.push(label);
-        label
-    }
-
-    fn current_loop(&mut self) -> Result<u32> {
-        let label = match self.loop_label_num.last() {
-            Some(label) => *label,
-            None => return Err("exitwhen is outside loop statement".into()),
-        };
-
-        Ok(label)
-    }
+fn err<T>(span: Span, message: impl Into<String>) -> Result<T> {
+    Err(Error::spanned(ErrorKind::Syntax, span, message))
+}
 
-    fn pop_loop(&mut self) -> u32 {
-        let label = self.loop_label_num.pop().expect("is not way!");
-        label
-    }
+/// 语句出错后，在块结束关键字或者行首的语句、声明关键字处恢复
+fn statement_sync(token: &Token, line_start: bool) -> bool {
+    token.is_block_end()
+        || line_start && (token.is_statement_start() || token.is_declaration_start())
 }
 
-#[derive(Clone)]
-struct Exp {
-    exp_type: ScriptType,
-    pos: u8,
-    #[allow(dead_code)]
-    priority: usize,
+/// 声明出错后，在 `endfunction`、`endglobals` 或者行首的声明关键字处恢复
+fn declaration_sync(token: &Token, line_start: bool) -> bool {
+    matches!(token, Token::Endfunction | Token::Endglobals)
+        || line_start && token.is_declaration_start()
 }
 
+/// `not` 的操作数可以包含算术运算，遇到比较和逻辑运算结束：`not a == b` 是 `(not a) == b`
+const NOT_PRIORITY: isize = 4;
+/// 负号只作用于紧跟的操作数：`-a * b` 是 `(-a) * b`
+const NEG_PRIORITY: isize = 7;
+
 #[rustfmt::skip]
 impl Token {
-    fn is_binop(&self) -> bool {
-        match &self {
-            Token::And | Token::Or | Token::Add | Token::Sub | Token::Mul
-            | Token::Div | Token::Equal | Token::NotEq | Token::LesEq
-            | Token::GreEq | Token::Less | Token::Greater
-              => true,
-            _ => false,
-        }
-    }
-
-    fn binop_bytecode(&self) -> fn(Reg, Reg, Reg) -> Bytecode {
-        match &self {
-            Token::And => Bytecode::And,
-            Token::Or => Bytecode::Or,
-            Token::Equal => Bytecode::Equal,
-            Token::NotEq => Bytecode::Notequal,
-            Token::LesEq => Bytecode::Lesserequal,
-            Token::GreEq => Bytecode::Greaterequal,
-            Token::Less => Bytecode::Lesser,
-            Token::Greater => Bytecode::Greater,
-            Token::Add => Bytecode::Add,
-            Token::Sub => Bytecode::Sub,
-            Token::Mul => Bytecode::Mul,
-            Token::Div => Bytecode::Div,
-            _ => panic!("invail binop")
-        }
+    fn binop(&self) -> Option<BinaryOp> {
+        let op = match self {
+            Token::And => BinaryOp::And,
+            Token::Or => BinaryOp::Or,
+            Token::Equal => BinaryOp::Equal,
+            Token::NotEq => BinaryOp::NotEq,
+            Token::LesEq => BinaryOp::LesEq,
+            Token::GreEq => BinaryOp::GreEq,
+            Token::Less => BinaryOp::Less,
+            Token::Greater => BinaryOp::Greater,
+            Token::Add => BinaryOp::Add,
+            Token::Sub => BinaryOp::Sub,
+            Token::Mul => BinaryOp::Mul,
+            Token::Div => BinaryOp::Div,
+            _ => return None,
+        };
+        Some(op)
     }
 
+    /// 二元运算符优先级，数字越大越先结合，同级左结合
     fn priority(&self) -> isize {
         match self {
-            Token::And => 1,
             Token::Or => 1,
-            Token::Equal => 2,
-            Token::NotEq => 2,
-            Token::LesEq => 2,
-            Token::GreEq => 2,
-            Token::Less => 2,
+            Token::And => 2,
+            Token::Equal => 3,
+            Token::NotEq => 3,
+            Token::LesEq => 3,
+            Token::GreEq => 3,
+            Token::Less => 3,
             Token::Greater => 3,
-            Token::Add => 3,
-            Token::Sub => 3,
-            Token::Div => 4,
-            Token::Mul => 4,
+            Token::Add => 5,
+            Token::Sub => 5,
+            Token::Div => 6,
+            Token::Mul => 6,
             _ => -1,
         }
     }
 
-}
-
-impl<R: Read> Parse<R> {
-    fn can_as_other(&self, one: &ScriptType, other: &ScriptType) -> Option<Option<ScriptType>> {
-        if one.array != other.array {
-            return None;
-        }
-
-        if one.name == "null" {
-            return Some(None);
-        }
-
-        if one == other {
-            return Some(None);
-        }
-
-        if (one.base == other.base) && other.extends == "" {
-            return Some(None);
-        }
-
-        if one.base == BytecodeValueType::Integer && other.name == "real" {
-            return Some(Some(other.clone()));
-        }
-
-        let one_name = one.name.clone();
-        let mut cur = one;
-        loop {
-            if cur.extends.len() == 0 {
-                return None;
-            }
-            if cur.extends == other.name {
-                return Some(None);
-            }
-            cur = self.typeinfo(cur.extends.as_str()).expect(
-                format!(
-                    "extends not exists type: {} extends {}",
-                    one_name.as_str(),
-                    cur.extends.as_str(),
-                )
-                .as_str(),
-            );
-        }
+    /// 可以作为表达式开头的token，用来判断 `return` 后面有没有返回值
+    fn starts_expression(&self) -> bool {
+        matches!(
+            self,
+            Token::String { .. } | Token::Null | Token::True | Token::False
+            | Token::Integer(_) | Token::RawCode { .. } | Token::Float(_)
+            | Token::ParL | Token::Name(_) | Token::Sub | Token::Not | Token::Function
+        )
     }
 
-    fn cast_to(&mut self, exp: Exp, target: ScriptType) -> Result<Exp> {
-        if exp.exp_type.base == BytecodeValueType::Integer
-            && Some(target) == self.typeinfo("real").cloned()
-        {
-            self.bytecodes.push(Bytecode::IntToReal(exp.pos.into()));
-            return Ok(exp);
-        }
-        Err("not match cast to method".into())
+    fn is_statement_start(&self) -> bool {
+        matches!(
+            self,
+            Token::Set | Token::Call | Token::If | Token::Loop | Token::Exitwhen
+            | Token::Return | Token::Debug | Token::Local
+        )
     }
-}
 
-impl<R: Read> Parse<R> {
-    fn do_binop(
-        &mut self,
-        token: &Token,
-        binop: fn(Reg, Reg, Reg) -> Bytecode,
-        left: Exp,
-        right: Exp,
-    ) -> Result<Exp> {
-        let reg = self.next_reg();
-        self.bytecodes
-            .push(binop(reg.into(), left.pos.into(), right.pos.into()));
-
-        let exp_type = {
-            if matches!(token, Token::Add | Token::Sub | Token::Mul | Token::Div) {
-                left.exp_type.clone()
-            } else if matches!(
-                token,
-                Token::Equal
-                    | Token::NotEq
-                    | Token::LesEq
-                    | Token::GreEq
-                    | Token::Less
-                    | Token::Greater
-                    | Token::And
-                    | Token::Or
-            ) {
-                self.typeinfo("boolean")
-                    .cloned()
-                    .expect("parser lack base type: boolean")
-            } else {
-                todo!()
-            }
-        };
-        return Ok(Exp {
-            exp_type,
-            pos: reg,
-            priority: 0,
-        });
+    fn is_block_end(&self) -> bool {
+        matches!(
+            self,
+            Token::Endfunction | Token::Endloop | Token::Endif | Token::Else | Token::Elseif
+        )
     }
 
-    fn binop_num(&mut self, binop: Token, left: Exp, right: Exp) -> Result<Exp> {
-        let op = binop.binop_bytecode();
-        let left_type_name = left.exp_type.name.clone();
-        let right_type_name = right.exp_type.name.clone();
-
-        if left_type_name == right_type_name
-            && left_type_name == "string"
-            && matches!(binop, Token::Add)
-        {
-            return self.do_binop(&binop, op, left, right);
-        }
-
-        if (left_type_name == "integer" || left_type_name == "real")
-            && (right_type_name == "integer" || right_type_name == "real")
-        {
-            if left_type_name == right_type_name {
-                // is ok
-            } else if left_type_name == "integer" {
-                self.bytecodes.push(Bytecode::IntToReal(left.pos.into()));
-            } else {
-                assert_eq!(right_type_name, "integer");
-                self.bytecodes.push(Bytecode::IntToReal(right.pos.into()));
-            }
-
-            return self.do_binop(&binop, op, left, right);
-        }
-
-        Err(format!(
-            "invail binop: {} {binop:?} {}",
-            left.exp_type.name, right.exp_type.name
+    fn is_declaration_start(&self) -> bool {
+        matches!(
+            self,
+            Token::Globals | Token::Type | Token::Native | Token::Constant | Token::Function
         )
-        .into())
     }
+}
 
-    fn binop(&mut self, binop: Token, left: Exp, right: Exp) -> Result<Exp> {
-        if matches!(
-            binop,
-            Token::Add
-                | Token::Sub
-                | Token::Mul
-                | Token::Div
-                | Token::LesEq
-                | Token::GreEq
-                | Token::Less
-                | Token::Greater
-        ) {
-            return self.binop_num(binop, left, right);
-        }
-
-        if matches!(binop, Token::Equal | Token::NotEq) {
-            if left.exp_type.base != right.exp_type.base
-                && left.exp_type.base != BytecodeValueType::Null
-                && right.exp_type.base != BytecodeValueType::Null
-            {
-                let left_type_name = left.exp_type.name.clone();
-                let right_type_name = right.exp_type.name.clone();
-
-                if (left_type_name == "integer" || left_type_name == "real")
-                    && (right_type_name == "integer" || right_type_name == "real")
-                {
-                    if left_type_name == right_type_name {
-                        // is ok
-                    } else if left_type_name == "integer" {
-                        self.bytecodes.push(Bytecode::IntToReal(left.pos.into()));
-                    } else {
-                        assert_eq!(right_type_name, "integer");
-                        self.bytecodes.push(Bytecode::IntToReal(right.pos.into()));
-                    }
-                } else {
-                    return Err(format!(
-                        "Type error {} cannot compare to {}",
-                        left.exp_type.name, right.exp_type.name
-                    )
-                    .into());
-                }
-            }
-            let op = binop.binop_bytecode();
-            return self.do_binop(&binop, op, left, right);
-        }
-
-        if matches!(binop, Token::And | Token::Or) {
-            if left.exp_type.base != BytecodeValueType::Boolean
-                || right.exp_type.base != BytecodeValueType::Boolean
-            {
-                return Err(format!(
-                    "Type error {} cannot and {}",
-                    left.exp_type.name, right.exp_type.name
-                )
-                .into());
-            }
-            let op = binop.binop_bytecode();
-            return self.do_binop(&binop, op, left, right);
+impl<S: Source> Parse<S> {
+    /// exp ::= name | int | float | exp + exp | exp - exp | exp * exp | exp / exp| funcall | ( exp ) | name[exp]
+    /// funcall ::= name ( explist )
+    ///
+    /// exp ::= (name | int | float) beta
+    /// beta ::= (+ exp| - exp | * exp | / exp | ( explist) )
+    fn expression(&mut self, op_priority: isize) -> Result<Expr> {
+        if !self.peek()?.starts_expression() {
+            let SpannedToken { token, span, .. } = self.lex.peek_spanned()?;
+            return err(*span, format!("not support exp: {token:?}"));
         }
-
-        Err(format!("invail binop:{binop:?}").into())
-    }
-
-    ///! exp ::= name | int | float | exp + exp | exp - exp | exp * exp | exp / exp| funcall | ( exp ) | name[exp]
-    ///! funcall ::= name ( explist )
-
-    ///! exp ::= (name | int | float) beta
-    ///!beta ::= (+ exp| - exp | * exp | / exp | ( explist) )
-    fn expression(&mut self, op_priority: isize) -> Result<Exp> {
-        let token = self.next()?;
-        let left = match token {
-            Token::String(v) => {
-                let str_index = self.add_literal_string(v)?;
-                let reg = self.next_reg();
-                self.bytecodes.push(Bytecode::SetRegLiteral(
-                    reg.into(),
-                    BytecodeValueType::String,
-                    str_index as u32,
-                ));
-
-                Exp {
-                    exp_type: self
-                        .typeinfo("string")
-                        .cloned()
-                        .expect("parser lack base type: string"),
-                    pos: reg,
-                    priority: 0,
-                }
+        let SpannedToken { token, span, .. } = self.next_spanned()?;
+        let kind = match token {
+            Token::String { value, .. } => {
+                let text = self.lex.decode(&value);
+                ExprKind::String(LiteralString { text, bytes: value })
             }
-            Token::Null => {
-                let reg = self.next_reg();
-                self.bytecodes.push(Bytecode::SetRegLiteral(
-                    reg.into(),
-                    BytecodeValueType::Null,
-                    0,
-                ));
-                Exp {
-                    exp_type: self
-                        .typeinfo("null")
-                        .cloned()
-                        .expect("parser lack base type: null"),
-                    pos: reg,
-                    priority: 0,
+            Token::Null => ExprKind::Null,
+            Token::True => ExprKind::Bool(true),
+            Token::False => ExprKind::Bool(false),
+            Token::Integer(i) => match u32::try_from(i) {
+                Ok(i) => ExprKind::Integer(i),
+                Err(_) => {
+                    let message = format!("integer literal out of 32-bit range: {i}");
+                    return Err(Error::spanned(ErrorKind::Lex, span, message));
                 }
-            }
-            Token::True | Token::False => {
-                let reg = self.next_reg();
-                self.bytecodes.push(Bytecode::SetRegLiteral(
-                    reg.into(),
-                    BytecodeValueType::Boolean,
-                    if token == Token::True { 1 } else { 0 },
-                ));
-                Exp {
-                    exp_type: self
-                        .typeinfo("boolean")
-                        .cloned()
-                        .expect("parser lack base type: boolean"),
-                    pos: reg,
-                    priority: 0,
-                }
-            }
-            Token::Integer(i) => {
-                let reg = self.next_reg();
-                self.bytecodes.push(Bytecode::SetRegLiteral(
-                    reg.into(),
-                    BytecodeValueType::Integer,
-                    i as u64 as u32,
-                ));
-                Exp {
-                    exp_type: self
-                        .typeinfo("integer")
-                        .cloned()
-                        .expect("parser lack base type: integer"),
-                    pos: reg,
-                    priority: 0,
-                }
-            }
-            Token::Float(i) => {
-                let reg = self.next_reg();
-                self.bytecodes.push(Bytecode::SetRegLiteral(
-                    reg.into(),
-                    BytecodeValueType::Real,
-                    (i as f32).to_bits(),
-                ));
-                Exp {
-                    exp_type: self
-                        .typeinfo("real")
-                        .cloned()
-                        .expect("parser lack base type: real"),
-                    pos: reg,
-                    priority: 0,
-                }
-            }
+            },
+            Token::RawCode { value, text } => ExprKind::RawCode { value, text },
+            Token::Float(f) => ExprKind::Real(f),
             Token::ParL => {
                 let exp = self.expression(0)?;
                 self.expect_consume(&Token::ParR)?;
-                exp
+                ExprKind::Paren(Box::new(exp))
             }
-            Token::Name(i) => {
+            Token::Name(name) => {
+                let name = Ident { name, span };
                 if self.guess(&Token::ParL)? {
-                    let func_idx = self.symbol_index(i.as_str())?;
-                    let func_ret = self.functioncall(func_idx)?;
-                    let ret_type = match func_ret {
-                        Some(ret_type) => ret_type,
-                        None => return Err("return nothing is not exp".into()),
-                    };
-                    let reg = self.next_reg();
-                    self.bytecodes.push(Bytecode::Move(reg.into(), 0.into()));
-                    Exp {
-                        exp_type: ret_type,
-                        pos: reg,
-                        priority: 0,
+                    ExprKind::Call(self.call(name)?)
+                } else if self.guess_and_consume(&Token::SqurL)? {
+                    let index = self.expression(0)?;
+                    self.expect_consume(&Token::SqurR)?;
+                    ExprKind::Index {
+                        name,
+                        index: Box::new(index),
                     }
                 } else {
-                    // var
-                    let symbol = self.symbol_index(i.as_str())?;
-                    let var_symbol = SymbolId(symbol as u32);
-                    let var_type = self.get_var_type(var_symbol)?.clone();
-
-                    let array = self.guess_and_consume(&Token::SqurL)?;
-                    if var_type.array != array {
-                        if array {
-                            return Err(format!(
-                                "cannot index a non-array value: {}",
-                                var_type.name
-                            )
-                            .into());
-                        } else {
-                            return Err(format!(
-                                "Assigning an array to a variable is not allowed: {}",
-                                var_type.name
-                            )
-                            .into());
-                        }
-                    }
-
-                    if array {
-                        let exp = self.expression(0)?;
-                        self.expect_consume(&Token::SqurR)?;
-                        let reg = self.next_reg();
-                        self.bytecodes.push(Bytecode::SetRegVarArray(
-                            reg.into(),
-                            exp.pos.into(),
-                            var_type.base,
-                            var_symbol,
-                        ));
-                        Exp {
-                            exp_type: ScriptType {
-                                array: false,
-                                ..var_type
-                            },
-                            pos: reg,
-                            priority: 0,
-                        }
-                    } else {
-                        let reg = self.next_reg();
-                        self.bytecodes.push(Bytecode::SetRegVar(
-                            reg.into(),
-                            var_type.base,
-                            var_symbol,
-                        ));
-                        Exp {
-                            exp_type: var_type,
-                            pos: reg,
-                            priority: 0,
-                        }
-                    }
-                }
-            }
-            Token::Sub => {
-                // negate
-                let exp = self.expression(0)?;
-                let exp_type = exp.exp_type.clone();
-                let reg = self.next_reg();
-                self.bytecodes.push(Bytecode::Negate(reg.into()));
-                Exp {
-                    exp_type,
-                    pos: reg,
-                    priority: 0,
-                }
-            }
-            Token::Not => {
-                // not expression
-                let exp = self.expression(0)?;
-                let exp_type = exp.exp_type.clone();
-                if exp_type.base != BytecodeValueType::Boolean {
-                    return Err("not expression must be followed by a boolean expression".into());
-                }
-                let reg = self.next_reg();
-                self.bytecodes.push(Bytecode::Not(reg.into()));
-                Exp {
-                    exp_type,
-                    pos: reg,
-                    priority: 0,
-                }
-            }
-            Token::Function => {
-                let exp_type = self
-                    .typeinfo("code")
-                    .expect("parser lack base type: code")
-                    .clone();
-                let (func_idx, _) = self.next_symbol()?;
-                let _ = self.find_function(func_idx)?;
-                let reg = self.next_reg();
-                self.bytecodes
-                    .push(Bytecode::SetRegCode(reg.into(), (func_idx as u32).into()));
-
-                Exp {
-                    exp_type,
-                    pos: reg,
-                    priority: 0,
+                    ExprKind::Var(name)
                 }
             }
-            _ => return Err(format!("not support exp: {token:?}").into()),
+            Token::Sub => ExprKind::Unary {
+                op: UnaryOp::Neg,
+                expr: Box::new(self.expression(NEG_PRIORITY)?),
+            },
+            Token::Not => ExprKind::Unary {
+                op: UnaryOp::Not,
+                expr: Box::new(self.expression(NOT_PRIORITY)?),
+            },
+            Token::Function => ExprKind::Code(self.ident()?),
+            _ => unreachable!("not support exp: {token:?}"),
         };
 
-        let token = self.peek()?.clone();
-        if !token.is_binop() {
-            return Ok(left);
+        let mut left = Expr {
+            kind,
+            span: span.to(self.span()),
         };
-
-        let mut left = left;
         loop {
             let token = self.peek()?.clone();
-            if !token.is_binop() {
+            let Some(binop) = token.binop() else {
                 return Ok(left);
             };
 
@@ -691,583 +380,476 @@ impl<R: Read> Parse<R> {
 
             // candidate is cur_op_priority
             // prev_exp ?op_priority ( cur_exp ?cur_op_priority otherexp )
-            let binop = self.next()?;
+            self.next()?;
             let cur_op_priority = candidate_op_priority;
             let right = self.expression(cur_op_priority)?;
-            left = self.binop(binop, left, right)?;
+            left = Expr {
+                span: left.span.to(right.span),
+                kind: ExprKind::Binary {
+                    op: binop,
+                    left: Box::new(left),
+                    right: Box::new(right),
+                },
+            };
         }
     }
 
-    fn next_symbol(&mut self) -> Result<(usize, String)> {
-        let token = self.next()?;
-        let name = match token {
-            Token::Name(name) => name,
-            _ => return Err(format!("unexpect symbol: {token:?}").into()),
-        };
-        let idx = self.symbol_index(name.as_str())?;
-        Ok((idx, name))
-    }
-
-    fn next_type(&mut self) -> Result<(usize, ScriptType)> {
-        let (next_idx, next_name) = self.next_symbol()?;
-        let script_type = match self.types.get(&next_idx) {
-            Some(script_type) => script_type.clone(),
-            None => return Err(format!("not found type: {next_name}").into()),
-        };
-        Ok((next_idx, script_type))
+    fn ident(&mut self) -> Result<Ident> {
+        let SpannedToken { token, span, .. } = self.lex.peek_spanned()?;
+        if !matches!(token, Token::Name(_)) {
+            return err(*span, format!("unexpect symbol: {token:?}"));
+        }
+        match self.next_spanned()? {
+            SpannedToken {
+                token: Token::Name(name),
+                span,
+                ..
+            } => Ok(Ident { name, span }),
+            _ => unreachable!(),
+        }
     }
 
-    fn find_function(&mut self, func_idx: usize) -> Result<(Token, &Function)> {
-        if self.functions.contains_key(&func_idx) || self.natives.contains_key(&func_idx) {
-            let function = self.functions.get(&func_idx);
-            let native = self.natives.get(&func_idx);
-            let is_func = function.is_some();
-            let call = if function.is_some() {
-                Token::Function
-            } else {
-                Token::Native
-            };
-            match if is_func { function } else { native } {
-                Some(func) => Ok((call, func)),
-                None => return Err("".into()),
+    /// funcall ::= name ( [exp {, exp}] )
+    fn call(&mut self, name: Ident) -> Result<Call> {
+        self.expect_consume(&Token::ParL)?;
+        let mut args = vec![];
+        if !self.guess(&Token::ParR)? {
+            loop {
+                args.push(self.expression(0)?);
+                match self.peek()? {
+                    Token::Comma => {
+                        self.next()?;
+                    }
+                    Token::ParR => break,
+                    _ => return self.unexpected("','"),
+                }
             }
-        } else {
-            return Err("not found function".into());
         }
+        self.expect_consume(&Token::ParR)?;
+        Ok(Call {
+            span: name.span.to(self.span()),
+            name,
+            args,
+        })
     }
 
-    fn if_statement(&mut self, ret: bool) -> Result<()> {
+    /// if_statement ::= if exp then chunk {elseif exp then chunk} [else chunk] endif
+    fn if_statement(&mut self) -> Result<StmtKind> {
         self.expect_consume(&Token::If)?;
-        let exp = self.expression(0)?;
-        self.expect_consume(&Token::Then)?;
-
-        self.bytecodes
-            .push(Bytecode::Jumpiffalse(exp.pos.into(), 0));
-        let jump = self.bytecodes.len() - 1;
-        let mut jumpelse = vec![];
-        let mut jumpend = vec![];
-        let mut has_else = false;
-        jumpelse.push(jump);
-
-        let mut jumpelse = jump;
+        let mut branches = vec![];
+        let mut else_body = None;
+        let mut cond = self.condition()?;
         loop {
-            let token = self.chunk(ret)?;
-            if token == Token::Endif {
-                break;
-            }
-
-            // jump to endif
-            self.bytecodes.push(Bytecode::Jump(0));
-            let jump = self.bytecodes.len() - 1;
-            jumpend.push(jump);
-            match token {
-                Token::Elseif => {
-                    // cond
-                    let jump_label = self.next_label();
-                    self.bytecodes.push(Bytecode::Label(jump_label as u32));
-                    match self.bytecodes.get_mut(jumpelse) {
-                        Some(Bytecode::Jumpiffalse(_, loc)) => *loc = jump_label,
-                        _ => panic!("expect Jumpiffalse"),
-                    };
-                    let exp = self.expression(0)?;
-                    self.bytecodes
-                        .push(Bytecode::Jumpiffalse(exp.pos.into(), 0));
-                    jumpelse = self.bytecodes.len() - 1;
-
-                    // then
-                    self.expect_consume(&Token::Then)?;
-                }
+            self.expect_consume(&Token::Then)?;
+            self.end_of_line()?;
+            let (body, end) = self.chunk()?;
+            branches.push(IfBranch { cond, body });
+            match end.token {
+                Token::Elseif => cond = self.condition()?,
                 Token::Else => {
-                    has_else = true;
-                    let jump_label = self.next_label();
-                    self.bytecodes.push(Bytecode::Label(jump_label as u32));
-                    match self.bytecodes.get_mut(jumpelse) {
-                        Some(Bytecode::Jumpiffalse(_, loc)) => *loc = jump_label,
-                        _ => panic!("expect Jumpiffalse"),
-                    };
+                    self.end_of_line()?;
+                    let (body, end) = self.chunk()?;
+                    if end.token != Token::Endif {
+                        return err(end.span, format!("expect endif, but {:?}", end.token));
+                    }
+                    else_body = Some(body);
+                    break;
+                }
+                Token::Endif => break,
+                token => {
+                    return err(
+                        end.span,
+                        format!("expect else | elseif | endif, but {token:?}"),
+                    )
                 }
-                _ => panic!("expect else/elseif"),
             }
         }
-
-        let jump_label = self.next_label();
-        self.bytecodes.push(Bytecode::Label(jump_label as u32));
-        for end in jumpend {
-            match self.bytecodes.get_mut(end) {
-                Some(Bytecode::Jump(loc)) => *loc = jump_label,
-                _ => panic!(""),
-            };
-        }
-
-        if !has_else {
-            match self.bytecodes.get_mut(jumpelse) {
-                Some(Bytecode::Jumpiffalse(_, loc)) => *loc = jump_label,
-                _ => panic!(""),
-            };
-        }
-
-        Ok(())
+        Ok(StmtKind::If(IfStmt {
+            branches,
+            else_body,
+        }))
     }
 
-    fn function_head(&mut self) -> Result<&Function> {
-        let token = self.next()?;
-        let function_token = match token {
-            Token::Function => Token::Function,
-            Token::Native => Token::Native,
-            _ => return Err(format!("expect function | native, but {token:?}").into()),
-        };
-
-        let (func_idx, func_name) = self.next_symbol()?;
-        if self.functions.contains_key(&func_idx) || self.natives.contains_key(&func_idx) {
-            return Err(format!("duplicate definition function :{func_name}").into());
+    /// 条件出错时跳到 `then`，保留整个if语句的结构
+    fn condition(&mut self) -> Result<Expr> {
+        let start = self.peek_span()?;
+        match self.expression(0) {
+            Ok(exp) => Ok(exp),
+            Err(e) => {
+                self.report(e)?;
+                self.synchronize(start.start, |token, line_start| {
+                    *token == Token::Then || statement_sync(token, line_start)
+                })?;
+                Ok(Expr {
+                    kind: ExprKind::Error,
+                    span: start.to(self.span()),
+                })
+            }
         }
+    }
 
-        let mut func = Function {
-            name: func_name,
-            args: vec![],
-            ret: None,
-        };
-        self.bytecodes
-            .push(Bytecode::Function(SymbolId(func_idx as u32)));
-
+    /// function_sig ::= name takes (nothing | type name {, type name}) returns (nothing | type)
+    fn function_sig(&mut self, constant: bool, start: Span) -> Result<FunctionSig> {
+        let name = self.ident()?;
         self.expect_consume(&Token::Takes)?;
-        let token = self.peek()?;
-        let is_nothing = matches!(token, Token::Nothing);
-
-        if !is_nothing {
+        let mut params = vec![];
+        if !self.guess_and_consume(&Token::Nothing)? {
             loop {
-                let idx = func.args.len() as u8;
-                if idx > 0 {
-                    let token = self.peek()?;
-                    match token {
+                if !params.is_empty() {
+                    match self.peek()? {
                         Token::Comma => {
                             self.next()?;
                         }
                         Token::Returns => break,
-                        _ => return Err("except ','".into()),
+                        _ => return err(self.peek_span()?, "expect ','"),
                     }
                 }
-
-                let (_, arg_type) = self.next_type()?;
-                let (arg_idx, arg_name) = self.next_symbol()?;
-                if function_token == Token::Function {
-                    self.set_var_type(SymbolId(arg_idx as u32), arg_type.clone());
-                    self.bytecodes.push(Bytecode::Funcarg(
-                        arg_type.base,
-                        idx,
-                        SymbolId(arg_idx as u32),
-                    ));
-                }
-                func.args.push(FunctionArg {
-                    name: arg_name,
-                    script_type: arg_type,
-                    idx,
-                });
+                let ty = self.ident()?;
+                let name = self.ident()?;
+                params.push(Param { ty, name });
             }
-        } else {
-            self.expect_consume(&Token::Nothing)?;
-        }
-
-        if func.args.len() > 256 {
-            return Err("too much param".into());
         }
 
         self.expect_consume(&Token::Returns)?;
-        let token = self.peek()?;
-        let is_nothing = matches!(token, Token::Nothing);
-        if !is_nothing {
-            let (_, ret_type) = self.next_type()?;
-            func.ret = Some(ret_type);
-        } else {
-            self.next()?;
-        }
-
-        let f = if function_token == Token::Function {
-            self.functions.insert(func_idx, func);
-            self.functions.get(&func_idx).unwrap()
+        let returns = if self.guess_and_consume(&Token::Nothing)? {
+            None
         } else {
-            assert!(function_token == Token::Native);
-            self.natives.insert(func_idx, func);
-            self.natives.get(&func_idx).unwrap()
+            Some(self.ident()?)
         };
 
-        Ok(f)
+        Ok(FunctionSig {
+            constant,
+            name,
+            params,
+            returns,
+            span: start.to(self.span()),
+        })
     }
 
-    fn set_statement(&mut self) -> Result<()> {
+    /// set_statement ::= set name [\[exp\]] = exp
+    fn set_statement(&mut self) -> Result<StmtKind> {
         self.expect_consume(&Token::Set)?;
-
-        // var
-        let (var_index, var_name) = self.next_symbol()?;
-        let var = self.get_var_type(SymbolId(var_index as u32))?.clone();
-        let array = self.guess_and_consume(&Token::SqurL)?;
-        if var.array != array {
-            if var.array {
-                return Err(
-                    format!("try set array variable into a nonarray variable: {var_name}").into(),
-                );
-            } else {
-                assert!(array);
-                return Err(format!("try set nonarray variable into a array: {var_name}").into());
-            }
-        }
-
-        let array_index = if array {
+        let name = self.ident()?;
+        let index = if self.guess_and_consume(&Token::SqurL)? {
             let exp = self.expression(0)?;
             self.expect_consume(&Token::SqurR)?;
-            Some(exp.pos)
+            Some(exp)
         } else {
             None
         };
 
         // '='
         self.expect_consume(&Token::Assign)?;
+        let value = self.expression(0)?;
+        Ok(StmtKind::Set { name, index, value })
+    }
 
-        // var
-        let exp = self.expression(0)?;
-        if let Some(i) = array_index {
-            self.bytecodes.push(Bytecode::SetVarArray(
-                i.into(),
-                exp.pos.into(),
-                SymbolId(var_index as u32),
-            ));
-        } else {
-            self.bytecodes
-                .push(Bytecode::SetVar(exp.pos.into(), SymbolId(var_index as u32)));
-        }
-
-        Ok(())
+    fn functioncall_statement(&mut self) -> Result<StmtKind> {
+        self.expect_consume(&Token::Call)?;
+        let name = self.ident()?;
+        Ok(StmtKind::Call(self.call(name)?))
     }
 
-    fn functioncall(&mut self, func_idx: usize) -> Result<Option<ScriptType>> {
-        let (op, func) = self.find_function(func_idx)?;
-        let op = if op == Token::Function {
-            Bytecode::Calljass
+    /// var_declared ::= [constant | local] type [array] name [= exp]
+    fn var_declared(&mut self) -> Result<VarDecl> {
+        let start = self.peek_span()?;
+        let kind = match self.peek()? {
+            Token::Constant => {
+                self.next()?;
+                VarKind::Constant
+            }
+            Token::Local => {
+                self.next()?;
+                VarKind::Local
+            }
+            _ => VarKind::Global,
+        };
+
+        let ty = self.ident()?;
+        let array = self.guess_and_consume(&Token::Array)?;
+        let name = self.ident()?;
+        let init = if self.guess_and_consume(&Token::Assign)? {
+            Some(self.expression(0)?)
         } else {
-            Bytecode::Callnative
+            None
         };
 
-        let func = func.clone();
-        let param_amount = func.args.len();
-        let func_ret = func.ret.clone();
-        let mut param = 0;
-        self.expect_consume(&Token::ParL)?;
-        while param < param_amount {
-            if param > 0 {
-                let token = self.peek()?;
-                match token {
-                    Token::ParR => break,
-                    Token::Comma => {
-                        self.next()?;
-                        {}
-                    }
-                    _ => return Err(format!("expect ',', but {token:?}").into()),
+        Ok(VarDecl {
+            kind,
+            ty,
+            array,
+            name,
+            init,
+            span: start.to(self.span()),
+        })
+    }
+
+    /// global_variables ::= global {var_declared} endglobal
+    fn global_variables(&mut self) -> Result<Globals> {
+        let start = self.peek_span()?;
+        self.expect_consume(&Token::Globals)?; //
+        self.end_of_line()?;
+        let mut vars = vec![];
+        loop {
+            self.skip_newlines()?;
+            let SpannedToken { token, span, .. } = self.lex.peek_spanned()?;
+            match token {
+                Token::Endglobals => {
+                    self.next()?;
+                    break;
                 }
+                Token::Eos | Token::Globals | Token::Type | Token::Native | Token::Function => {
+                    return self.unexpected("Endglobals");
+                }
+                _ => {}
+            }
+            let start = span.start;
+            let res = self
+                .var_declared()
+                .map(|var| vars.push(var))
+                .and_then(|_| self.end_of_line());
+            if let Err(e) = res {
+                self.report(e)?;
+                self.synchronize(start + 1, |token, line_start| {
+                    *token == Token::Endglobals || line_start
+                })?;
             }
-
-            param += 1;
-            let mut exp = self.expression(0)?;
-            let arg = match func.args.get(param - 1) {
-                Some(arg) => arg,
-                None => return Err("function call params amount is incorrect!".into()),
-            };
-            let as_other = self.can_as_other(&exp.exp_type, &arg.script_type);
-            let Some(as_other) = as_other else {
-                return Err(format!(
-                    "Type error: {} cannot as {}",
-                    exp.exp_type.name, arg.script_type.name
-                )
-                .into());
-            };
-            if let Some(as_other) = as_other {
-                exp = self.cast_to(exp, as_other)?;
-            };
-
-            self.bytecodes.push(Bytecode::Push(exp.pos.into()));
-        }
-        self.expect_consume(&Token::ParR)?;
-        if param != param_amount {
-            return Err("function call params amount is incorrect!".into());
         }
-        self.bytecodes.push(op(SymbolId(func_idx as u32)));
-        self.bytecodes.push(Bytecode::Popn(param_amount as u8));
-        Ok(func_ret)
+        Ok(Globals {
+            vars,
+            span: start.to(self.span()),
+        })
     }
 
-    fn functioncall_statement(&mut self) -> Result<()> {
-        self.expect_consume(&Token::Call)?;
-        let (func_idx, _) = self.next_symbol()?;
-        self.functioncall(func_idx)?;
-        Ok(())
+    /// type_definition ::= type name extends name
+    fn type_definition(&mut self) -> Result<TypeDecl> {
+        let start = self.peek_span()?;
+        self.expect_consume(&Token::Type)?;
+        let name = self.ident()?;
+        self.expect_consume(&Token::Extends)?;
+        let extends = self.ident()?;
+        Ok(TypeDecl {
+            name,
+            extends,
+            span: start.to(self.span()),
+        })
     }
 
-    ///! var_declared ::= [constant | local] type name = exp
-    fn var_declared(&mut self) -> Result<()> {
-        let op = match self.peek()? {
-            Token::Constant => Bytecode::Constant,
-            Token::Local => Bytecode::Local,
-            _ => Bytecode::Global,
+    /// native ::= [constant] native function_sig
+    /// udf ::= [constant] function function_sig { var_declared } chunk
+    fn function_definition(&mut self) -> Result<Decl> {
+        let start = self.peek_span()?;
+        let constant = self.guess_and_consume(&Token::Constant)?;
+        let SpannedToken { token, span, .. } = self.next_spanned()?;
+        let native = match token {
+            Token::Native => true,
+            Token::Function => false,
+            _ => return err(span, format!("expect function | native, but {token:?}")),
         };
-
-        if op != Bytecode::Global {
-            self.next()?;
+        let sig = self.function_sig(constant, start)?;
+        if native {
+            return Ok(Decl::Native(NativeDecl {
+                span: sig.span,
+                sig,
+            }));
         }
 
-        // type
-        let (type_index, type_name) = self.next_symbol()?;
-        let mut script_type = match self.types.get(&type_index) {
-            Some(st) => st.clone(),
-            None => return Err(format!("not found type: {type_name}").into()),
-        };
-
-        let array = self.guess_and_consume(&Token::Array)?;
-        if array {
-            script_type.array = true;
-            if !matches!(
-                script_type.base,
-                BytecodeValueType::Integer
-                    | BytecodeValueType::Real
-                    | BytecodeValueType::String
-                    | BytecodeValueType::Boolean
-                    | BytecodeValueType::Handle
-            ) {
-                return Err("var array only int, real, string, boolean, handle".into());
+        self.end_of_line()?;
+        let mut locals = vec![];
+        while self.guess(&Token::Local)? {
+            let start = self.peek_span()?.start;
+            let res = self
+                .var_declared()
+                .map(|var| locals.push(var))
+                .and_then(|_| self.end_of_line());
+            if let Err(e) = res {
+                self.report(e)?;
+                self.synchronize(start + 1, statement_sync)?;
             }
         }
 
-        // self.expect_consume(&Token::Assign)?;
-
-        // var
-        let var_index = self.next_symbol()?.0;
-        let bytecode = op(script_type.base, SymbolId(var_index as u32));
-        self.bytecodes.push(bytecode);
-        self.set_var_type(SymbolId(var_index as u32), script_type);
-
-        if !self.guess_and_consume(&Token::Assign)? {
-            // 只定义变量不赋值
-            return Ok(());
+        let (body, end) = self.chunk()?;
+        if end.token != Token::Endfunction {
+            return err(end.span, format!("expect endfunction, but {:?}", end.token));
         }
+        Ok(Decl::Function(FunctionDecl {
+            sig,
+            locals,
+            body,
+            span: start.to(self.span()),
+        }))
+    }
 
-        if array {
-            return Err("array is not allow init".into());
-        }
-
-        let exp = self.expression(0)?;
-        self.bytecodes
-            .push(Bytecode::SetVar(exp.pos.into(), SymbolId(var_index as u32)));
-
-        Ok(())
+    /// return_statement ::= return [exp]
+    fn return_statement(&mut self) -> Result<StmtKind> {
+        self.expect_consume(&Token::Return)?;
+        let value = if self.peek()?.starts_expression() {
+            Some(self.expression(0)?)
+        } else {
+            None
+        };
+        Ok(StmtKind::Return(value))
     }
 
-    ///! global_variables ::= global {var_declared} endglobal
-    fn global_variables(&mut self) -> Result<()> {
-        self.expect_consume(&Token::Globals)?; //
-        loop {
-            let token = self.peek()?;
-            match token {
-                Token::Endglobals => {
-                    self.next()?;
-                    break;
-                }
-                _ => self.var_declared()?,
-            }
+    /// loop_statement ::= loop chunk endloop
+    fn loop_statement(&mut self) -> Result<StmtKind> {
+        self.expect_consume(&Token::Loop)?;
+        self.end_of_line()?;
+        let (body, end) = self.chunk()?;
+        if end.token != Token::Endloop {
+            return err(end.span, format!("expect endloop, but {:?}", end.token));
         }
-        Ok(())
+        Ok(StmtKind::Loop(body))
     }
 
-    fn type_definition(&mut self) -> Result<()> {
-        self.next()?; // type
-        let derived = self.next_symbol()?; // name
-        self.expect_consume(&Token::Extends)?; //extends
-        let (base_index, base_name) = self.next_symbol()?; // name
-
-        let base = match self.types.get(&base_index) {
-            Some(st) => st.clone(),
-            None => return Err(format!("not found type: {base_index} {base_name}").into()),
-        };
-        self.types.insert(
-            derived.0,
-            ScriptType {
-                name: derived.1.clone(),
-                extends: base.name.clone(),
-                base: base.base.clone(),
-                array: false,
-            },
-        );
-        self.bytecodes.push(Bytecode::Type(derived.0.into()));
-        self.bytecodes.push(Bytecode::Extends(base_index.into()));
-        Ok(())
+    /// debug_statement ::= debug (set_statement | call_statement | if_statement | loop_statement | return_statement)
+    fn debug_statement(&mut self) -> Result<StmtKind> {
+        self.expect_consume(&Token::Debug)?;
+        if !matches!(
+            self.peek()?,
+            Token::Set | Token::Call | Token::If | Token::Loop | Token::Return
+        ) {
+            return self.unexpected("set | call | if | loop | return after debug");
+        }
+        Ok(StmtKind::Debug(Box::new(self.statement()?)))
     }
 
-    fn native_function(&mut self) -> Result<()> {
-        let constants = self.guess(&Token::Constant)?;
-        if constants {
-            self.next()?;
-        }
-        self.function_head()?;
-        Ok(())
+    fn statement(&mut self) -> Result<Stmt> {
+        let start = self.peek_span()?;
+        let kind = match self.peek()? {
+            Token::Set => self.set_statement()?,
+            Token::Call => self.functioncall_statement()?,
+            Token::Return => self.return_statement()?,
+            Token::Loop => self.loop_statement()?,
+            Token::Exitwhen => {
+                self.next()?;
+                StmtKind::Exitwhen(self.expression(0)?)
+            }
+            Token::If => self.if_statement()?,
+            Token::Debug => self.debug_statement()?,
+            _ => {
+                let SpannedToken { token, span, .. } = self.lex.peek_spanned()?;
+                return err(*span, format!("invail token: {token:?}"));
+            }
+        };
+        Ok(Stmt {
+            kind,
+            span: start.to(self.span()),
+        })
     }
 
-    ///! udf ::= function name takes type name {, type name} returns type { var_declared } { stat } [return] endfunction
-    fn user_defined_function(&mut self) -> Result<()> {
-        let ret = self.function_head()?.ret.is_some();
+    /// chunk ::= {stat} (endfunction | endloop | else | elseif | endif)
+    /// 返回语句和结束这段语句的token
+    fn chunk(&mut self) -> Result<(Vec<Stmt>, SpannedToken)> {
+        let mut body = vec![];
         loop {
-            let token = self.peek()?;
-            match token {
-                Token::Local => {
-                    self.var_declared()?;
-                }
-                _ => {
-                    break;
-                }
+            self.skip_newlines()?;
+            let SpannedToken { token, span, .. } = self.lex.peek_spanned()?;
+            if token.is_block_end() {
+                return Ok((body, self.next_spanned()?));
+            }
+            if *token == Token::Eos || token.is_declaration_start() {
+                // 缺少结束关键字，交给外层恢复
+                return self.unexpected("statement");
+            }
+            let start = span.start;
+            let res = self
+                .statement()
+                .map(|stmt| body.push(stmt))
+                .and_then(|_| self.end_of_line());
+            if let Err(e) = res {
+                self.report(e)?;
+                self.synchronize(start + 1, statement_sync)?;
             }
         }
-
-        self.chunk(ret)?;
-        Ok(())
     }
 
-    fn chunk(&mut self, ret: bool) -> Result<Token> {
+    /// BNF
+    /// file ::= {global declarations}
+    /// global declarations ::= global_variables | type_definition | native_function | user_defined_function
+    pub fn parse_file(&mut self) -> Result<File> {
+        let mut file = File::default();
         loop {
-            let token = self.peek()?;
-            match token {
-                Token::Set => {
-                    self.set_statement()?;
-                }
-                Token::Call => {
-                    self.functioncall_statement()?;
-                }
-                Token::Return => {
-                    self.next()?;
-                    self.bytecodes.push(Bytecode::Return);
-                    if ret {
-                        self.expression(0)?;
-                    }
-                }
-                Token::Endfunction => {
-                    self.next()?;
-                    return Ok(Token::Endfunction);
-                }
-                Token::Loop => {
-                    self.next()?;
-                    let label = self.push_loop();
-                    self.bytecodes.push(Bytecode::Label(label));
-                    let result = self.chunk(ret)?;
-                    if result != Token::Endloop {
-                        return Err(format!("invail chunk").into());
-                    }
-                    self.pop_loop();
-                }
-                Token::Exitwhen => {
+            self.skip_newlines()?;
+            let SpannedToken { token, span, .. } = self.lex.peek_spanned()?;
+            if *token == Token::Eos {
+                break;
+            }
+            let start = span.start;
+            let res = self
+                .declaration()
+                .map(|decl| file.decls.push(decl))
+                .and_then(|_| self.end_of_line());
+            if let Err(e) = res {
+                self.report(e)?;
+                self.synchronize(start + 1, declaration_sync)?;
+                if matches!(self.peek()?, Token::Endfunction | Token::Endglobals) {
                     self.next()?;
-                    let exp = self.expression(0)?;
-                    let ok = exp.exp_type.base == BytecodeValueType::Boolean;
-                    if !ok {
-                        return Err("exitwhen expect a boolean expression".into());
-                    }
-                    let label = self.current_loop()?;
-                    self.bytecodes
-                        .push(Bytecode::Jumpiftrue(exp.pos.into(), label));
-                }
-                Token::If => {
-                    self.if_statement(ret)?;
-                }
-                Token::Endloop | Token::Else | Token::Elseif | Token::Endif => {
-                    return Ok(self.next()?);
                 }
-                _ => return Err(format!("invail token: {token:?}").into()),
             }
         }
+        let lex_errors = self.lex.take_errors();
+        self.errors.extend(lex_errors);
+        self.errors.sort_by_key(|e| e.span().map(|span| span.start));
+        Ok(file)
     }
 
-    ///! BNF
-    ///! file ::= {global declarations}
-    ///! global declarations ::= global_variables | type_definition | native_function | user_defined_function
-    pub fn file(&mut self) -> Result<()> {
-        loop {
-            let token = self.peek()?;
-            match token {
-                Token::Globals => self.global_variables()?,
-                Token::Type => self.type_definition()?,
-                Token::Native | Token::Constant => self.native_function()?,
-                Token::Function => self.user_defined_function()?,
-                Token::Eos => break,
-                _ => return Err(format!("unexpect statement {token:?}").into()),
+    /// global declarations ::= global_variables | type_definition | native_function | user_defined_function
+    fn declaration(&mut self) -> Result<Decl> {
+        let decl = match self.peek()? {
+            Token::Globals => Decl::Globals(self.global_variables()?),
+            Token::Type => Decl::Type(self.type_definition()?),
+            Token::Native | Token::Constant | Token::Function => self.function_definition()?,
+            _ => {
+                let SpannedToken { token, span, .. } = self.lex.peek_spanned()?;
+                return err(*span, format!("unexpect statement {token:?}"));
             }
+        };
+        Ok(decl)
+    }
+
+    /// 解析整个文件并生成字节码。错误恢复模式下有任何语法错误都不生成字节码，
+    /// 返回第一个错误，全部错误（包括生成字节码时的错误）通过 `errors` 获取
+    pub fn file(&mut self) -> Result<()> {
+        let file = self.parse_file()?;
+        if let Some(e) = self.errors.first() {
+            return Err(e.clone());
+        }
+        if !self.recovery {
+            return self.codegen.file(&file);
+        }
+        let errors = self.codegen.file_with_recovery(&file);
+        self.errors.extend(errors);
+        match self.errors.first() {
+            Some(e) => Err(e.clone()),
+            None => Ok(()),
         }
-        Ok(())
     }
 }
 
-impl<R: Read> Parse<R> {
-    fn with_basetype(mut self, symbol: &str, base: BytecodeValueType) -> Result<Parse<R>> {
-        let symbol_idx = self.symbol_index(symbol)?;
-        self.types.insert(
-            symbol_idx,
-            ScriptType {
-                name: symbol.to_string(),
-                extends: "".to_string(),
-                base,
-                array: false,
-            },
-        );
-        Ok(self)
+impl<S: Source> Parse<S> {
+    /// 解析 `lex` 产生的token，类型表中只有基础类型
+    pub fn from_lex(lex: Lex<S>) -> Parse<S> {
+        Parse::new(lex)
     }
+}
 
-    pub fn test_instance(r: R) -> Result<Parse<R>> {
-        let new = Parse::new(r)
-            .with_basetype("code", BytecodeValueType::Code)?
-            .with_basetype("integer", BytecodeValueType::Integer)?
-            .with_basetype("real", BytecodeValueType::Real)?
-            .with_basetype("string", BytecodeValueType::String)?
-            .with_basetype("handle", BytecodeValueType::Handle)?
-            .with_basetype("boolean", BytecodeValueType::Boolean)?
-            .with_basetype("integer", BytecodeValueType::Integer)?
-            .with_basetype("null", BytecodeValueType::Null)?;
-        Ok(new)
+impl<R: Read> Parse<ReadSource<R>> {
+    pub fn test_instance(r: R) -> Result<Parse<ReadSource<R>>> {
+        Ok(Parse::from_lex(Lex::new(r)))
     }
+}
 
-    pub fn show(&self) {
-        let bytecodes = &self.bytecodes;
-        let symbol_table = &self.symbol_table;
-        let types = &self.types;
-        let functions = &self.functions;
-        let strings = &self.strings;
-        let line = &self.lex.line();
-        let col = &self.lex.col();
-        println!("symbol_table:");
-        for (idx, symbol) in symbol_table.iter().enumerate() {
-            let t = types.get(&idx).cloned();
-            let f = functions.get(&idx).cloned();
-
-            let t = t
-                .and_then(|t| Some(format!(" {t:?}")))
-                .unwrap_or("".to_string());
-            let f = f
-                .and_then(|t| Some(format!(" {t:?}")))
-                .unwrap_or("".to_string());
-            println!("{idx}:{symbol}{t}{f}")
-        }
-        println!("");
-        println!("");
-        println!("string literal:");
-        for (idx, str) in strings.iter().enumerate() {
-            println!("{idx}:{str}")
-        }
-        println!("");
-        println!("");
-        println!("bytecode:");
-        for (idx, bytecode) in bytecodes.iter().enumerate() {
-            println!("{idx}:{bytecode:?}")
-        }
-
-        println!("end {line}:{col}");
+impl<'a> Parse<SliceRead<'a>> {
+    /// 直接解析内存中的源码，比 `test_instance` 快
+    pub fn from_slice(input: &'a [u8]) -> Parse<SliceRead<'a>> {
+        Parse::from_lex(Lex::from_slice(input, FileId::default()))
     }
+}
 
+impl<S: Source> Parse<S> {
     pub fn show_pos(&self) {
-        let line = &self.lex.line();
-        let col = &self.lex.col();
-        println!("end {line}:{col}");
+        println!("end {}", self.span());
     }
 }
 
@@ -1278,7 +860,7 @@ fn test_type_def() -> Result<()> {
     let input_str = "type agent			    extends     handle \n type event			    extends     agent";
     let mut parse = Parse::test_instance(Cursor::new(input_str))?;
     parse.file()?;
-    parse.show();
+    print!("{}", parse.program());
 
     Ok(())
 }
@@ -1290,7 +872,7 @@ fn test_native_func_def() -> Result<()> {
     let input_str = "constant native GetObjectName               takes integer objectId          returns string\nnative GetObjectName2               takes integer objectId          returns string";
     let mut parse = Parse::test_instance(Cursor::new(input_str))?;
     parse.file()?;
-    parse.show();
+    print!("{}", parse.program());
 
     Ok(())
 }
@@ -1303,7 +885,7 @@ fn test_var_def() -> Result<()> {
         "globals \n constant integer b = 20 \n constant integer a = 5 + 10 * b \n endglobals";
     let mut parse = Parse::test_instance(Cursor::new(input_str))?;
     parse.file()?;
-    parse.show();
+    print!("{}", parse.program());
 
     Ok(())
 }
@@ -1315,7 +897,7 @@ fn test_string_literal() -> Result<()> {
     let input_str = "globals \n constant string ss = \"abcd\" \n endglobals";
     let mut parse = Parse::test_instance(Cursor::new(input_str))?;
     parse.file()?;
-    parse.show();
+    print!("{}", parse.program());
 
     Ok(())
 }
@@ -1328,7 +910,7 @@ fn test_loop() -> Result<()> {
         "function Main takes nothing returns nothing \n loop loop endloop endloop \n endfunction";
     let mut parse = Parse::test_instance(Cursor::new(input_str))?;
     parse.file()?;
-    parse.show();
+    print!("{}", parse.program());
 
     Ok(())
 }
@@ -1341,7 +923,7 @@ fn test_loop_exitwhen() -> Result<()> {
         "function Main takes nothing returns nothing \n loop loop exitwhen 1 == 1 endloop exitwhen true endloop \n endfunction";
     let mut parse = Parse::test_instance(Cursor::new(input_str))?;
     parse.file()?;
-    parse.show();
+    print!("{}", parse.program());
 
     Ok(())
 }
@@ -1354,7 +936,280 @@ fn test_if_statement() -> Result<()> {
         "function Main takes nothing returns nothing \n local integer i local integer j if true then set i = 5 set j = 6 elseif false then set i = 10 else set j = 7 endif \n endfunction";
     let mut parse = Parse::test_instance(Cursor::new(input_str))?;
     parse.file()?;
-    parse.show();
+    print!("{}", parse.program());
 
     Ok(())
 }
+
+#[test]
+fn test_newline_statement() -> Result<()> {
+    use std::io::Cursor;
+
+    let input_str = "globals\n integer a\n integer b\nendglobals\nfunction Main takes nothing returns nothing\n set a = 1 set b = 2\nendfunction\n";
+    let mut parse = Parse::test_instance(Cursor::new(input_str))?;
+    parse.file()?;
+
+    let mut parse = Parse::test_instance(Cursor::new(input_str))?.with_newlines(true);
+    let err = parse.file().unwrap_err();
+    let span = err.span().unwrap();
+    assert_eq!((span.start_line, span.start_col), (6, 12));
+
+    let input_str = "\n\nglobals\n\n integer a // a\nendglobals\n\nfunction Main takes nothing returns nothing\n local integer i = 0\n\n loop\n exitwhen i > 10\n if i == 1 then\n set a = i\n elseif i == 2 then\n set a = 2\n else\n set a = 3\n endif\n set i = i + 1\n endloop\nendfunction";
+    let mut parse = Parse::test_instance(Cursor::new(input_str))?.with_newlines(true);
+    parse.file()?;
+
+    let input_str = "globals integer a\nendglobals";
+    let mut parse = Parse::test_instance(Cursor::new(input_str))?.with_newlines(true);
+    assert!(parse.file().is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_ast() -> Result<()> {
+    use crate::ast::Expr;
+
+    let input_str = b"type unit extends handle\nglobals\n integer array a\nendglobals\nconstant function F takes integer x, real y returns integer\n local integer i = -x\n if x > 1 then\n set a[i] = F(x - 1, y) * 2\n elseif x == 1 then\n return 1\n else\n call F(0, y)\n endif\n return i\nendfunction";
+    let mut parse = Parse::from_slice(input_str);
+    let file = parse.parse_file()?;
+    assert_eq!(file.decls.len(), 3);
+    let Decl::Type(unit) = &file.decls[0] else {
+        panic!("expect type");
+    };
+    assert_eq!((&*unit.name.name, &*unit.extends.name), ("unit", "handle"));
+    let Decl::Globals(globals) = &file.decls[1] else {
+        panic!("expect globals");
+    };
+    assert!(globals.vars[0].array);
+    assert_eq!(globals.vars[0].kind, VarKind::Global);
+
+    let Decl::Function(function) = &file.decls[2] else {
+        panic!("expect function");
+    };
+    assert!(function.sig.constant);
+    assert_eq!(function.sig.params.len(), 2);
+    assert_eq!(
+        function.sig.returns.as_ref().unwrap().name.as_ref(),
+        "integer"
+    );
+    assert_eq!(function.locals[0].kind, VarKind::Local);
+    assert!(matches!(
+        function.locals[0].init,
+        Some(Expr {
+            kind: ExprKind::Unary {
+                op: UnaryOp::Neg,
+                ..
+            },
+            ..
+        })
+    ));
+    assert_eq!(function.body.len(), 2);
+    let StmtKind::If(stmt) = &function.body[0].kind else {
+        panic!("expect if");
+    };
+    assert_eq!(stmt.branches.len(), 2);
+    assert!(stmt.else_body.is_some());
+    let StmtKind::Set { index, value, .. } = &stmt.branches[0].body[0].kind else {
+        panic!("expect set");
+    };
+    assert!(index.is_some());
+    let ExprKind::Binary { op, left, .. } = &value.kind else {
+        panic!("expect binary");
+    };
+    assert_eq!(*op, BinaryOp::Mul);
+    assert!(matches!(&left.kind, ExprKind::Call(call) if call.args.len() == 2));
+    assert_eq!(
+        &input_str[value.span.start..value.span.end],
+        b"F(x - 1, y) * 2"
+    );
+    assert!(matches!(
+        function.body[1].kind,
+        StmtKind::Return(Some(Expr {
+            kind: ExprKind::Var(_),
+            ..
+        }))
+    ));
+    assert_eq!(
+        &input_str[function.span.start..function.span.end],
+        &input_str[input_str.len() - function.span.len()..]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_error_span() -> Result<()> {
+    use std::io::Cursor;
+
+    let input_str =
+        "function Main takes nothing returns nothing \n local integer i \n set j = 5 \n endfunction";
+    let mut parse = Parse::test_instance(Cursor::new(input_str))?;
+    let err = parse.file().unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::UnknownSymbol);
+    assert_eq!(err.code(), "E0301");
+    let span = err.span().expect("parse error has location");
+    assert_eq!((span.start_line, span.start_col), (3, 6));
+    assert_eq!(&input_str[span.start..span.end], "j");
+
+    Ok(())
+}
+
+#[test]
+fn test_error_recovery() -> Result<()> {
+    let input_str = b"globals
+integer a = )
+integer b = 1
+endglobals
+function A takes nothing returns nothing
+local integer i = )
+set i = 
+if + then
+call A()
+endif
+call A(
+endfunction
+function B takes nothing returns nothing
+set x = 1 $
+endfunction
+type
+function C takes nothing returns integer
+return 1
+endfunction
+";
+    let mut parse = Parse::from_slice(input_str).with_recovery(true);
+    let file = parse.parse_file()?;
+    let names: Vec<_> = file
+        .decls
+        .iter()
+        .filter_map(|decl| match decl {
+            Decl::Function(f) => Some(&*f.sig.name.name),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(names, ["A", "B", "C"]);
+    let Decl::Globals(globals) = &file.decls[0] else {
+        panic!("expect globals")
+    };
+    assert_eq!(globals.vars.len(), 1);
+    assert_eq!(&*globals.vars[0].name.name, "b");
+
+    let lines: Vec<_> = parse
+        .errors()
+        .iter()
+        .map(|e| (e.span().unwrap().start_line, e.kind()))
+        .collect();
+    use ErrorKind::*;
+    assert_eq!(
+        lines,
+        [
+            (2, Syntax),
+            (6, Syntax),
+            (8, Syntax),
+            (8, Syntax),
+            (12, Syntax),
+            (14, Lex),
+            (17, Syntax)
+        ]
+    );
+    assert!(parse.file().is_err());
+
+    // 不开启错误恢复时遇到第一个错误就返回
+    let mut parse = Parse::from_slice(input_str);
+    let err = parse.file().unwrap_err();
+    assert_eq!(err.span().unwrap().start_line, 2);
+    assert!(parse.errors().is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn test_operator_precedence() -> Result<()> {
+    fn sexp(expr: &Expr) -> String {
+        match &expr.kind {
+            ExprKind::Var(name) => name.name.to_string(),
+            ExprKind::Integer(i) => i.to_string(),
+            ExprKind::Bool(b) => b.to_string(),
+            ExprKind::Paren(expr) => sexp(expr),
+            ExprKind::Call(call) => {
+                let args: Vec<_> = call.args.iter().map(sexp).collect();
+                format!("{}({})", call.name.name, args.join(", "))
+            }
+            ExprKind::Unary { op, expr } => format!("({op:?} {})", sexp(expr)),
+            ExprKind::Binary { op, left, right } => {
+                format!("({op:?} {} {})", sexp(left), sexp(right))
+            }
+            kind => panic!("unexpected {kind:?}"),
+        }
+    }
+
+    let table = [
+        ("a + b * c", "(Add a (Mul b c))"),
+        ("a * b + c", "(Add (Mul a b) c)"),
+        ("a - b - c", "(Sub (Sub a b) c)"),
+        ("a / b / c", "(Div (Div a b) c)"),
+        ("a - b + c", "(Add (Sub a b) c)"),
+        ("a * b / c", "(Div (Mul a b) c)"),
+        ("(a - b) - c", "(Sub (Sub a b) c)"),
+        ("a - (b - c)", "(Sub a (Sub b c))"),
+        ("-a + b", "(Add (Neg a) b)"),
+        ("-a * b", "(Mul (Neg a) b)"),
+        ("a * -b", "(Mul a (Neg b))"),
+        ("- -a", "(Neg (Neg a))"),
+        ("-(a + b)", "(Neg (Add a b))"),
+        ("-f(a) + 1", "(Add (Neg f(a)) 1)"),
+        ("a + b > c", "(Greater (Add a b) c)"),
+        ("a > b + c", "(Greater a (Add b c))"),
+        ("a < b == c", "(Equal (Less a b) c)"),
+        ("a > b == c", "(Equal (Greater a b) c)"),
+        ("a >= b != c <= d", "(LesEq (NotEq (GreEq a b) c) d)"),
+        ("a == b and c", "(And (Equal a b) c)"),
+        ("a and b == c", "(And a (Equal b c))"),
+        ("a or b and c", "(Or a (And b c))"),
+        ("a and b or c", "(Or (And a b) c)"),
+        ("a and b and c", "(And (And a b) c)"),
+        ("a or b or c", "(Or (Or a b) c)"),
+        ("not a and b", "(And (Not a) b)"),
+        ("not a or b", "(Or (Not a) b)"),
+        ("a and not b", "(And a (Not b))"),
+        ("not not a", "(Not (Not a))"),
+        ("not a == b", "(Equal (Not a) b)"),
+        ("not (a == b)", "(Not (Equal a b))"),
+        ("not a + b", "(Not (Add a b))"),
+        ("a == -b * c", "(Equal a (Mul (Neg b) c))"),
+        (
+            "a + b * c > d or not e and f",
+            "(Or (Greater (Add a (Mul b c)) d) (And (Not e) f))",
+        ),
+        ("f(a + b, -c) * 2", "(Mul f((Add a b), (Neg c)) 2)"),
+    ];
+    for (input, expected) in table {
+        let mut parse = Parse::from_slice(input.as_bytes());
+        let expr = parse.expression(0)?;
+        assert_eq!(sexp(&expr), expected, "{input}");
+        assert_eq!(parse.peek()?, &Token::Eos, "{input}");
+        assert_eq!(expr.span.len(), input.len(), "{input}");
+    }
+    Ok(())
+}
+
+#[test]
+fn test_trace() -> Result<()> {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    let tokens = Rc::new(RefCell::new(vec![]));
+    let traced = tokens.clone();
+    let mut parse = Parse::from_slice(b"type unit extends handle")
+        .with_trace(move |token| traced.borrow_mut().push(token.token.clone()));
+    parse.file()?;
+    assert_eq!(
+        *tokens.borrow(),
+        [
+            Token::Type,
+            Token::Name("unit".into()),
+            Token::Extends,
+            Token::Name("handle".into()),
+        ]
+    );
+    Ok(())
+}
@@ -0,0 +1,273 @@
+use crate::Span;
+use crate::TypeId;
+use crate::TypeTable;
+use garygo_jass_common::Bytecode;
+use garygo_jass_common::BytecodeValueType;
+use garygo_jass_common::SymbolId;
+use std::collections::HashMap;
+use std::fmt::Display;
+
+/// 变量、参数和表达式的类型：类型表中的类型，或者它的数组
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ScriptType {
+    pub(crate) id: TypeId,
+    /// 和类型表中的基础类型一致，避免每次都查表
+    pub(crate) base: BytecodeValueType,
+    pub(crate) array: bool,
+}
+
+impl ScriptType {
+    pub fn id(&self) -> TypeId {
+        self.id
+    }
+
+    /// 字节码中使用的基础类型
+    pub fn base(&self) -> BytecodeValueType {
+        self.base
+    }
+
+    pub fn is_array(&self) -> bool {
+        self.array
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FunctionArg {
+    pub(crate) name: String,
+    pub(crate) script_type: ScriptType,
+    #[allow(dead_code)]
+    pub(crate) idx: u8,
+}
+
+impl FunctionArg {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn script_type(&self) -> &ScriptType {
+        &self.script_type
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Function {
+    pub(crate) name: String,
+    pub(crate) args: Vec<FunctionArg>,
+    pub(crate) ret: Option<ScriptType>,
+    /// 函数名的位置，用于错误提示
+    pub(crate) span: Span,
+    pub(crate) constant: bool,
+}
+
+impl Function {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn args(&self) -> &[FunctionArg] {
+        &self.args
+    }
+
+    /// 返回值类型，`returns nothing` 为 `None`
+    pub fn ret(&self) -> Option<&ScriptType> {
+        self.ret.as_ref()
+    }
+
+    /// 函数名在源码中的位置
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    pub fn is_constant(&self) -> bool {
+        self.constant
+    }
+}
+
+/// 字符串表中的一项，`text` 是按源码编码解码后的内容，`bytes` 保留源码中的原始字节
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LiteralString {
+    pub text: String,
+    pub bytes: Vec<u8>,
+}
+
+/// 符号表，用哈希表把名字映射到下标。下标按第一次出现的顺序连续分配，就是字节码中的 `SymbolId`
+#[derive(Clone, Default)]
+pub(crate) struct SymbolTable {
+    pub(crate) names: Vec<String>,
+    index: HashMap<String, usize>,
+}
+
+impl SymbolTable {
+    /// 名字的下标，第一次出现时分配新的下标
+    pub(crate) fn intern(&mut self, name: &str) -> usize {
+        if let Some(idx) = self.index.get(name) {
+            return *idx;
+        }
+        let idx = self.names.len();
+        self.names.push(name.to_string());
+        self.index.insert(name.to_string(), idx);
+        idx
+    }
+
+    pub(crate) fn get(&self, name: &str) -> Option<usize> {
+        self.index.get(name).copied()
+    }
+}
+
+/// 编译结果：符号表、字符串表、类型、函数和字节码。
+/// 类型和函数都按符号在符号表中的下标索引，和字节码中的 `SymbolId` 一致
+#[derive(Clone, Default)]
+pub struct Program {
+    pub(crate) bytecodes: Vec<Bytecode>,
+    pub(crate) symbol_table: SymbolTable,
+    pub(crate) types: TypeTable,
+    pub(crate) functions: HashMap<usize, Function>,
+    pub(crate) natives: HashMap<usize, Function>,
+    pub(crate) strings: Vec<LiteralString>,
+}
+
+impl Program {
+    pub fn bytecodes(&self) -> &[Bytecode] {
+        &self.bytecodes
+    }
+
+    /// 符号表，下标就是 `SymbolId`
+    pub fn symbols(&self) -> &[String] {
+        &self.symbol_table.names
+    }
+
+    pub fn symbol(&self, id: SymbolId) -> Option<&str> {
+        self.symbol_table
+            .names
+            .get(id.0 as usize)
+            .map(String::as_str)
+    }
+
+    pub fn symbol_id(&self, name: &str) -> Option<SymbolId> {
+        self.symbol_table.get(name).map(SymbolId::from)
+    }
+
+    /// 字符串表，下标就是字节码中字符串字面量的值
+    pub fn strings(&self) -> &[LiteralString] {
+        &self.strings
+    }
+
+    /// 用户定义的函数
+    pub fn function(&self, name: &str) -> Option<&Function> {
+        self.functions.get(&(self.symbol_id(name)?.0 as usize))
+    }
+
+    pub fn native(&self, name: &str) -> Option<&Function> {
+        self.natives.get(&(self.symbol_id(name)?.0 as usize))
+    }
+
+    /// 类型表，包括基础类型
+    pub fn types(&self) -> &TypeTable {
+        &self.types
+    }
+
+    pub fn functions(&self) -> impl Iterator<Item = (SymbolId, &Function)> {
+        by_symbol(&self.symbol_table.names, &self.functions)
+    }
+
+    pub fn natives(&self) -> impl Iterator<Item = (SymbolId, &Function)> {
+        by_symbol(&self.symbol_table.names, &self.natives)
+    }
+}
+
+fn by_symbol<'a, T>(
+    symbol_table: &'a [String],
+    map: &'a HashMap<usize, T>,
+) -> impl Iterator<Item = (SymbolId, &'a T)> {
+    (0..symbol_table.len()).filter_map(|idx| Some((idx.into(), map.get(&idx)?)))
+}
+
+/// 和旧的 `show` 输出一致：符号表、字符串表和字节码
+impl Display for Program {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "symbol_table:")?;
+        for (idx, symbol) in self.symbol_table.names.iter().enumerate() {
+            write!(f, "{idx}:{symbol}")?;
+            if let Some(id) = self.types.lookup(symbol) {
+                match self.types.get(id).parent() {
+                    Some(parent) => {
+                        let parent = self.types.name(parent);
+                        write!(f, " ScriptType({symbol} extends {parent})")?
+                    }
+                    None => write!(f, " ScriptType({symbol})")?,
+                }
+            }
+            if let Some(func) = self.functions.get(&idx) {
+                write!(f, " function {}(", func.name)?;
+                for arg in &func.args {
+                    write!(f, " {} {}", self.types.name(arg.script_type.id), arg.name)?;
+                }
+                match func.ret {
+                    Some(ret) => write!(f, ") => Some(ScriptType({}))", self.types.name(ret.id))?,
+                    None => write!(f, ") => None")?,
+                }
+            }
+            writeln!(f)?;
+        }
+        writeln!(f)?;
+        writeln!(f)?;
+        writeln!(f, "string literal:")?;
+        for (idx, str) in self.strings.iter().enumerate() {
+            writeln!(f, "{idx}:{}", str.text)?;
+        }
+        writeln!(f)?;
+        writeln!(f)?;
+        writeln!(f, "bytecode:")?;
+        for (idx, bytecode) in self.bytecodes.iter().enumerate() {
+            writeln!(f, "{idx}:{bytecode:?}")?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_program() -> crate::Result<()> {
+    use crate::Parse;
+
+    let input = b"type unit extends handle\nnative GetUnit takes integer id returns unit\nconstant function F takes unit u returns integer\nreturn 1\nendfunction\nglobals\nstring s = \"hi\"\nendglobals\n";
+    let mut parse = Parse::from_slice(input);
+    parse.file()?;
+    let program = parse.into_program();
+
+    let unit = program.types().lookup("unit").unwrap();
+    assert_eq!(program.types().get(unit).parent(), Some(TypeId::HANDLE));
+
+    let native = program.native("GetUnit").unwrap();
+    assert_eq!(native.args()[0].name(), "id");
+    assert_eq!(native.ret().map(ScriptType::id), Some(unit));
+    assert_eq!(native.ret().unwrap().base(), BytecodeValueType::Handle);
+    assert!(program.function("GetUnit").is_none());
+
+    let func = program.function("F").unwrap();
+    assert!(func.is_constant());
+    assert_eq!(func.args()[0].script_type().id(), unit);
+    assert_eq!((func.span().start_line, func.span().start_col), (3, 19));
+
+    let id = program.symbol_id("F").unwrap();
+    assert_eq!(program.symbol(id), Some("F"));
+    let names: Vec<_> = program.functions().map(|(_, func)| func.name()).collect();
+    assert_eq!(names, ["F"]);
+    assert_eq!(program.strings()[0].text, "hi");
+    assert!(matches!(
+        program.bytecodes().last(),
+        Some(Bytecode::SetVar(..))
+    ));
+    assert!(program.to_string().contains(":F function F( unit u)"));
+    Ok(())
+}
+
+#[test]
+fn test_symbol_table() {
+    let mut table = SymbolTable::default();
+    assert_eq!(table.intern("integer"), 0);
+    assert_eq!(table.intern("real"), 1);
+    assert_eq!(table.intern("integer"), 0);
+    assert_eq!(table.get("real"), Some(1));
+    assert_eq!(table.get("unit"), None);
+    assert_eq!(table.names, ["integer", "real"]);
+}
@@ -0,0 +1,43 @@
+use std::fmt::Display;
+
+/// 源文件编号，用于区分token属于哪个输入文件
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct FileId(pub u32);
+
+/// 源码区间，`start..end` 是字节偏移，行列从1开始，结束位置不包含在区间内
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Span {
+    pub file: FileId,
+    pub start: usize,
+    pub end: usize,
+    pub start_line: usize,
+    pub start_col: usize,
+    pub end_line: usize,
+    pub end_col: usize,
+}
+
+impl Span {
+    /// 从当前区间开始，到 `end` 区间结束
+    pub fn to(self, end: Span) -> Span {
+        Span {
+            end: end.end,
+            end_line: end.end_line,
+            end_col: end.end_col,
+            ..self
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+}
+
+impl Display for Span {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.start_line, self.start_col)
+    }
+}
@@ -0,0 +1,273 @@
+use crate::ast::TypeDecl;
+use crate::Error;
+use crate::ErrorKind;
+use crate::Result;
+use crate::Span;
+use garygo_jass_common::BytecodeValueType;
+use std::collections::HashMap;
+
+/// 类型在类型表中的编号，按定义顺序分配，基础类型的编号是固定的
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct TypeId(pub u32);
+
+impl TypeId {
+    pub const CODE: TypeId = TypeId(0);
+    pub const INTEGER: TypeId = TypeId(1);
+    pub const REAL: TypeId = TypeId(2);
+    pub const STRING: TypeId = TypeId(3);
+    pub const HANDLE: TypeId = TypeId(4);
+    pub const BOOLEAN: TypeId = TypeId(5);
+    pub const NULL: TypeId = TypeId(6);
+}
+
+/// 类型表中的一个类型
+#[derive(Debug, Clone)]
+pub struct TypeDef {
+    name: String,
+    parent: Option<TypeId>,
+    base: BytecodeValueType,
+    span: Option<Span>,
+}
+
+impl TypeDef {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// `extends` 的类型，基础类型没有父类型
+    pub fn parent(&self) -> Option<TypeId> {
+        self.parent
+    }
+
+    /// 字节码中使用的基础类型，handle的子类型都是handle
+    pub fn base(&self) -> BytecodeValueType {
+        self.base
+    }
+
+    /// 类型名在源码中的位置，基础类型没有位置
+    pub fn span(&self) -> Option<Span> {
+        self.span
+    }
+}
+
+/// 类型表，记录所有类型和以handle为根的子类型关系
+#[derive(Debug, Clone)]
+pub struct TypeTable {
+    defs: Vec<TypeDef>,
+    by_name: HashMap<String, TypeId>,
+    children: Vec<Vec<TypeId>>,
+}
+
+impl Default for TypeTable {
+    fn default() -> Self {
+        TypeTable::new()
+    }
+}
+
+impl TypeTable {
+    /// 只有基础类型的类型表，编号和 `TypeId` 中的常量一致
+    pub fn new() -> TypeTable {
+        let mut table = TypeTable {
+            defs: vec![],
+            by_name: HashMap::new(),
+            children: vec![],
+        };
+        for (name, base) in [
+            ("code", BytecodeValueType::Code),
+            ("integer", BytecodeValueType::Integer),
+            ("real", BytecodeValueType::Real),
+            ("string", BytecodeValueType::String),
+            ("handle", BytecodeValueType::Handle),
+            ("boolean", BytecodeValueType::Boolean),
+            ("null", BytecodeValueType::Null),
+        ] {
+            table.insert(name, None, base, None);
+        }
+        table
+    }
+
+    fn insert(
+        &mut self,
+        name: &str,
+        parent: Option<TypeId>,
+        base: BytecodeValueType,
+        span: Option<Span>,
+    ) -> TypeId {
+        let id = TypeId(self.defs.len() as u32);
+        self.defs.push(TypeDef {
+            name: name.to_string(),
+            parent,
+            base,
+            span,
+        });
+        self.by_name.insert(name.to_string(), id);
+        self.children.push(vec![]);
+        if let Some(parent) = parent {
+            self.children[parent.0 as usize].push(id);
+        }
+        id
+    }
+
+    /// 定义 `type name extends parent`，父类型必须已经定义并且是handle或它的子类型
+    pub(crate) fn define(&mut self, decl: &TypeDecl) -> Result<TypeId> {
+        let (name, parent) = (&decl.name, &decl.extends);
+        let existing = self.lookup(&name.name);
+        let parent_id = self.lookup(&parent.name);
+        let cyclic = name.name == parent.name
+            || existing
+                .zip(parent_id)
+                .is_some_and(|(id, p)| self.is_subtype(p, id));
+        if cyclic {
+            let message = format!(
+                "cyclic type hierarchy: {} extends {}",
+                name.name, parent.name
+            );
+            return Err(self.with_first_definition(
+                Error::spanned(ErrorKind::InvalidDefinition, parent.span, message),
+                existing,
+            ));
+        }
+        if existing.is_some() {
+            let message = format!("duplicate definition type: {}", name.name);
+            return Err(self.with_first_definition(
+                Error::spanned(ErrorKind::DuplicateDefinition, name.span, message),
+                existing,
+            ));
+        }
+        let Some(parent_id) = parent_id else {
+            let message = format!("not found type: {}", parent.name);
+            return Err(Error::spanned(ErrorKind::UnknownType, parent.span, message));
+        };
+        let base = self.get(parent_id).base;
+        if base != BytecodeValueType::Handle {
+            let message = format!("type {} must extend handle, but {}", name.name, parent.name);
+            return Err(Error::spanned(
+                ErrorKind::InvalidDefinition,
+                parent.span,
+                message,
+            ));
+        }
+        Ok(self.insert(&name.name, Some(parent_id), base, Some(name.span)))
+    }
+
+    fn with_first_definition(&self, e: Error, id: Option<TypeId>) -> Error {
+        match id.and_then(|id| self.get(id).span) {
+            Some(span) => e.with_label(span, "first defined here"),
+            None => e,
+        }
+    }
+
+    pub fn get(&self, id: TypeId) -> &TypeDef {
+        &self.defs[id.0 as usize]
+    }
+
+    pub fn lookup(&self, name: &str) -> Option<TypeId> {
+        self.by_name.get(name).copied()
+    }
+
+    pub fn name(&self, id: TypeId) -> &str {
+        &self.get(id).name
+    }
+
+    pub fn len(&self) -> usize {
+        self.defs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.defs.is_empty()
+    }
+
+    /// 按定义顺序排列的所有类型
+    pub fn iter(&self) -> impl Iterator<Item = (TypeId, &TypeDef)> {
+        (0..).map(TypeId).zip(&self.defs)
+    }
+
+    /// 直接继承 `id` 的类型
+    pub fn children(&self, id: TypeId) -> &[TypeId] {
+        &self.children[id.0 as usize]
+    }
+
+    /// 从 `id` 自己开始，依次到根类型
+    pub fn ancestors(&self, id: TypeId) -> impl Iterator<Item = TypeId> + '_ {
+        std::iter::successors(Some(id), |id| self.get(*id).parent)
+    }
+
+    /// `sub` 是否是 `sup` 或它的子类型
+    pub fn is_subtype(&self, sub: TypeId, sup: TypeId) -> bool {
+        self.ancestors(sub).any(|id| id == sup)
+    }
+
+    /// 两个类型最近的公共父类型，不在同一棵树上时为 `None`
+    pub fn common_supertype(&self, a: TypeId, b: TypeId) -> Option<TypeId> {
+        self.ancestors(a).find(|id| self.is_subtype(b, *id))
+    }
+
+    /// `id` 的所有子类型，不包括自己，按深度优先顺序排列
+    pub fn subtypes(&self, id: TypeId) -> Vec<TypeId> {
+        let mut out = vec![];
+        let mut stack: Vec<_> = self.children(id).iter().rev().copied().collect();
+        while let Some(id) = stack.pop() {
+            out.push(id);
+            stack.extend(self.children(id).iter().rev());
+        }
+        out
+    }
+}
+
+#[test]
+fn test_type_table() -> Result<()> {
+    use crate::Parse;
+
+    let input = b"type agent extends handle\ntype widget extends agent\ntype unit extends widget\ntype item extends widget\ntype player extends agent\n";
+    let mut parse = Parse::from_slice(input);
+    parse.file()?;
+    let types = parse.program().types();
+    let id = |name| types.lookup(name).unwrap();
+    let (agent, widget, unit, item, player) = (
+        id("agent"),
+        id("widget"),
+        id("unit"),
+        id("item"),
+        id("player"),
+    );
+
+    assert_eq!(types.get(unit).parent(), Some(widget));
+    assert_eq!(types.get(unit).base(), BytecodeValueType::Handle);
+    assert_eq!(types.get(TypeId::HANDLE).parent(), None);
+    assert!(types.is_subtype(unit, TypeId::HANDLE));
+    assert!(types.is_subtype(unit, unit));
+    assert!(!types.is_subtype(widget, unit));
+    assert!(!types.is_subtype(TypeId::INTEGER, TypeId::HANDLE));
+    assert_eq!(types.common_supertype(unit, item), Some(widget));
+    assert_eq!(types.common_supertype(unit, player), Some(agent));
+    assert_eq!(types.common_supertype(unit, TypeId::INTEGER), None);
+    assert_eq!(types.subtypes(widget), [unit, item]);
+    assert_eq!(types.subtypes(agent), [widget, unit, item, player]);
+    let chain: Vec<_> = types.ancestors(unit).map(|id| types.name(id)).collect();
+    assert_eq!(chain, ["unit", "widget", "agent", "handle"]);
+
+    let kind = |input: &[u8]| Parse::from_slice(input).file().unwrap_err().kind();
+    let cases: [(&[u8], ErrorKind); 5] = [
+        (b"type a extends a", ErrorKind::InvalidDefinition),
+        (
+            b"type a extends handle\ntype b extends a\ntype a extends b",
+            ErrorKind::InvalidDefinition,
+        ),
+        (
+            b"type a extends handle\ntype a extends handle",
+            ErrorKind::DuplicateDefinition,
+        ),
+        (b"type a extends widget", ErrorKind::UnknownType),
+        (b"type a extends integer", ErrorKind::InvalidDefinition),
+    ];
+    for (input, expect) in cases {
+        assert_eq!(kind(input), expect, "{}", String::from_utf8_lossy(input));
+    }
+
+    let e = Parse::from_slice(b"type a extends handle\ntype b extends a\ntype a extends b")
+        .file()
+        .unwrap_err();
+    assert_eq!(e.span().unwrap().start_line, 3);
+    assert_eq!(e.labels()[0].span.start_line, 1);
+    Ok(())
+}
@@ -0,0 +1,352 @@
+use crate::ast::File;
+use crate::Codegen;
+use crate::Error;
+use crate::ErrorKind;
+use crate::FileId;
+use crate::Lex;
+use crate::Parse;
+use crate::Program;
+use crate::Result;
+use crate::SourceFile;
+use crate::SourceMap;
+
+/// 编译单元，按顺序编译多个源文件（如common.j、blizzard.j、war3map.j）。
+/// 每个文件单独分词和解析，位置都属于各自的文件，后面的文件可以使用前面文件的声明
+pub struct CompilationUnit {
+    sources: SourceMap,
+    /// 已经编译过的源文件个数，包括预编译环境中的文件
+    compiled: usize,
+    files: Vec<File>,
+    codegen: Codegen,
+    newlines: bool,
+    recovery: bool,
+    errors: Vec<Error>,
+    /// 上一次 `compile` 是否失败，失败后不能做成预编译环境
+    failed: bool,
+}
+
+impl Default for CompilationUnit {
+    fn default() -> Self {
+        CompilationUnit::new()
+    }
+}
+
+impl CompilationUnit {
+    pub fn new() -> Self {
+        CompilationUnit {
+            sources: SourceMap::new(),
+            compiled: 0,
+            files: vec![],
+            codegen: Codegen::new(),
+            newlines: false,
+            recovery: false,
+            errors: vec![],
+            failed: false,
+        }
+    }
+
+    /// debug构建保留 `debug` 开头的语句
+    pub fn with_debug(mut self, debug: bool) -> Self {
+        self.codegen = self.codegen.with_debug(debug);
+        self
+    }
+
+    /// 严格按行解析，见 `Parse::with_newlines`
+    pub fn with_newlines(mut self, newlines: bool) -> Self {
+        self.newlines = newlines;
+        self
+    }
+
+    /// 出错后继续解析，收集所有文件的错误，见 `Parse::with_recovery`
+    pub fn with_recovery(mut self, recovery: bool) -> Self {
+        self.recovery = recovery;
+        self
+    }
+
+    /// 按编译顺序添加源文件，源码按文件自己的编码解码
+    pub fn add_source(&mut self, file: SourceFile) -> FileId {
+        self.sources.add(file)
+    }
+
+    pub fn sources(&self) -> &SourceMap {
+        &self.sources
+    }
+
+    /// 本单元解析的源文件的语法树，按加入顺序排列，不包含预编译环境中的文件
+    pub fn files(&self) -> &[File] {
+        &self.files
+    }
+
+    pub fn codegen(&self) -> &Codegen {
+        &self.codegen
+    }
+
+    /// 编译结果，包含预编译环境中的声明
+    pub fn program(&self) -> &Program {
+        self.codegen.program()
+    }
+
+    pub fn into_program(self) -> Program {
+        self.codegen.into_program()
+    }
+
+    /// 错误恢复模式下收集到的所有错误
+    pub fn errors(&self) -> &[Error] {
+        &self.errors
+    }
+
+    pub fn take_errors(&mut self) -> Vec<Error> {
+        std::mem::take(&mut self.errors)
+    }
+
+    /// 依次解析所有还没有编译的文件，都没有语法错误时再按顺序生成字节码。
+    /// 错误恢复模式下语义错误也按声明收集，返回第一个错误，全部错误通过 `errors` 获取
+    pub fn compile(&mut self) -> Result<()> {
+        let res = self.compile_sources();
+        self.failed = res.is_err();
+        res
+    }
+
+    fn compile_sources(&mut self) -> Result<()> {
+        let start = self.files.len();
+        for idx in self.compiled..self.sources.len() {
+            let id = FileId(idx as u32);
+            let source = self.sources.file(id).expect("file added to source map");
+            let lex = Lex::from_slice(source.text(), id).with_encoding(source.encoding());
+            let mut parse = Parse::from_lex(lex)
+                .with_newlines(self.newlines)
+                .with_recovery(self.recovery);
+            let file = parse.parse_file()?;
+            self.errors.extend(parse.take_errors());
+            self.files.push(file);
+        }
+        self.compiled = self.sources.len();
+        if let Some(e) = self.errors.first() {
+            return Err(e.clone());
+        }
+
+        for file in &self.files[start..] {
+            if self.recovery {
+                self.errors.extend(self.codegen.file_with_recovery(file));
+            } else {
+                self.codegen.file(file)?;
+            }
+        }
+        match self.errors.first() {
+            Some(e) => Err(e.clone()),
+            None => Ok(()),
+        }
+    }
+
+    /// 把已经编译的文件做成预编译环境，用于编译多个使用相同common.j、blizzard.j的地图脚本。
+    /// 上一次编译失败或者还有没编译的文件时返回错误
+    pub fn snapshot(&self) -> Result<Prelude> {
+        if self.failed || self.compiled < self.sources.len() {
+            return Err(Error::new(
+                ErrorKind::NotCompiled,
+                "snapshot requires all sources to be compiled successfully",
+            ));
+        }
+        let mut codegen = self.codegen.clone();
+        codegen.take_warnings();
+        Ok(Prelude {
+            sources: self.sources.clone(),
+            compiled: self.compiled,
+            codegen,
+        })
+    }
+}
+
+/// 预编译环境，包含已编译文件中的类型、native、函数、全局变量和字符串表。
+/// 克隆不会重新解析源码，源码本身也是共享的。
+/// 环境只保存在内存中，还不支持序列化到磁盘，字节码和类型表都没有稳定的存储格式
+#[derive(Clone)]
+pub struct Prelude {
+    sources: SourceMap,
+    compiled: usize,
+    codegen: Codegen,
+}
+
+impl Prelude {
+    /// 按顺序编译预编译环境的源文件，有错误时返回第一个错误
+    pub fn compile(files: impl IntoIterator<Item = SourceFile>) -> Result<Prelude> {
+        Prelude::compile_unit(CompilationUnit::new(), files)
+    }
+
+    /// 用配置好的编译单元编译，如 `CompilationUnit::new().with_debug(true)`。
+    /// debug设置会沿用到环境创建的单元
+    pub fn compile_unit(
+        mut unit: CompilationUnit,
+        files: impl IntoIterator<Item = SourceFile>,
+    ) -> Result<Prelude> {
+        for file in files {
+            unit.add_source(file);
+        }
+        unit.compile()?;
+        unit.snapshot()
+    }
+
+    pub fn sources(&self) -> &SourceMap {
+        &self.sources
+    }
+
+    pub fn codegen(&self) -> &Codegen {
+        &self.codegen
+    }
+
+    /// 编译结果，包含预编译环境中的声明
+    pub fn program(&self) -> &Program {
+        self.codegen.program()
+    }
+
+    pub fn into_program(self) -> Program {
+        self.codegen.into_program()
+    }
+
+    /// 基于预编译环境的新编译单元，新加入的文件可以使用环境中的声明，报错时也能显示环境中的源码
+    pub fn unit(&self) -> CompilationUnit {
+        CompilationUnit {
+            sources: self.sources.clone(),
+            compiled: self.compiled,
+            codegen: self.codegen.clone(),
+            ..CompilationUnit::new()
+        }
+    }
+}
+
+#[test]
+fn test_compilation_unit() -> Result<()> {
+    let mut unit = CompilationUnit::new();
+    let common = unit.add_source(SourceFile::new(
+        "common.j",
+        b"type agent extends handle\nnative F takes integer a returns nothing\n".to_vec(),
+    ));
+    let map = unit.add_source(SourceFile::new(
+        "war3map.j",
+        b"function Main takes nothing returns nothing\ncall F(1)\nendfunction\n".to_vec(),
+    ));
+    unit.compile()?;
+    assert_eq!(unit.files().len(), 2);
+    assert_eq!(unit.files()[0].decls[1].span().file, common);
+    let span = unit.files()[1].decls[0].span();
+    assert_eq!((span.file, span.start_line), (map, 1));
+
+    // 错误位置属于出错的文件，附加位置指向声明所在的文件
+    let mut unit = CompilationUnit::new().with_recovery(true);
+    unit.add_source(SourceFile::new(
+        "common.j",
+        b"native F takes integer a returns nothing\n".to_vec(),
+    ));
+    let map = unit.add_source(SourceFile::new(
+        "war3map.j",
+        b"\nfunction Main takes nothing returns nothing\ncall F()\nendfunction\n".to_vec(),
+    ));
+    let e = unit.compile().unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::ArityMismatch);
+    let span = e.span().unwrap();
+    assert_eq!((span.file, span.start_line), (map, 3));
+    assert_eq!(e.labels()[0].span.file, common);
+    assert_eq!(e.labels()[0].span.start_line, 1);
+    assert_eq!(unit.errors().len(), 1);
+
+    // 语义错误按声明收集，不会在第一个出错的函数停下
+    let mut unit = CompilationUnit::new().with_recovery(true);
+    unit.add_source(SourceFile::new(
+        "war3map.j",
+        b"function A takes nothing returns nothing\ncall F()\nendfunction\nfunction B takes nothing returns integer\nreturn 1.5\nendfunction\n".to_vec(),
+    ));
+    assert!(unit.compile().is_err());
+    let kinds: Vec<_> = unit.errors().iter().map(|e| e.kind()).collect();
+    assert_eq!(kinds, [ErrorKind::UnknownSymbol, ErrorKind::TypeMismatch]);
+
+    // 多个文件的语法错误都会收集
+    let mut unit = CompilationUnit::new().with_recovery(true);
+    unit.add_source(SourceFile::new(
+        "a.j",
+        b"globals\ninteger = 1\nendglobals".to_vec(),
+    ));
+    unit.add_source(SourceFile::new("b.j", b"native takes nothing".to_vec()));
+    assert!(unit.compile().is_err());
+    let files: Vec<_> = unit
+        .errors()
+        .iter()
+        .map(|e| e.span().unwrap().file)
+        .collect();
+    assert_eq!(files, [FileId(0), FileId(1)]);
+    Ok(())
+}
+
+#[test]
+fn test_prelude() -> Result<()> {
+    let prelude = Prelude::compile([SourceFile::new(
+        "common.j",
+        b"native F takes integer a returns nothing\nglobals\nconstant integer N = 1\nendglobals\n"
+            .to_vec(),
+    )])?;
+
+    let mut unit = prelude.unit();
+    let map = unit.add_source(SourceFile::new(
+        "war3map.j",
+        b"function Main takes nothing returns nothing\ncall F(N)\nendfunction\n".to_vec(),
+    ));
+    assert_eq!(map, FileId(1));
+    unit.compile()?;
+    assert_eq!(unit.files().len(), 1);
+
+    // 每个单元互不影响，上一个地图的函数不会出现在环境中
+    let mut unit = prelude.unit().with_recovery(true);
+    unit.add_source(SourceFile::new(
+        "war3map.j",
+        b"function Main takes nothing returns nothing\ncall F()\nendfunction\nfunction Other takes nothing returns nothing\ncall Main()\nendfunction\n".to_vec(),
+    ));
+    let e = unit.compile().unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::ArityMismatch);
+    assert_eq!(e.labels()[0].span.file, FileId(0));
+
+    let mut unit = prelude.unit();
+    unit.add_source(SourceFile::new(
+        "war3map.j",
+        b"function Other takes nothing returns nothing\ncall Main()\nendfunction\n".to_vec(),
+    ));
+    assert_eq!(unit.compile().unwrap_err().kind(), ErrorKind::UnknownSymbol);
+
+    // 编译失败或者还有没编译的文件时不能做成环境
+    assert_eq!(
+        unit.snapshot().err().map(|e| e.kind()),
+        Some(ErrorKind::NotCompiled)
+    );
+    let mut unit = prelude.unit();
+    unit.add_source(SourceFile::new("war3map.j", b"".to_vec()));
+    assert_eq!(
+        unit.snapshot().err().map(|e| e.kind()),
+        Some(ErrorKind::NotCompiled)
+    );
+    unit.compile()?;
+    unit.snapshot()?;
+
+    // debug环境创建的单元保留debug语句
+    let common = || {
+        SourceFile::new(
+            "common.j",
+            b"native F takes integer a returns nothing\n".to_vec(),
+        )
+    };
+    let map = || {
+        SourceFile::new(
+            "war3map.j",
+            b"function Main takes nothing returns nothing\ndebug call F(1)\nendfunction\n".to_vec(),
+        )
+    };
+    let mut sizes = vec![];
+    for prelude in [
+        Prelude::compile([common()])?,
+        Prelude::compile_unit(CompilationUnit::new().with_debug(true), [common()])?,
+    ] {
+        let mut unit = prelude.unit();
+        unit.add_source(map());
+        unit.compile()?;
+        sizes.push(unit.program().bytecodes().len());
+    }
+    assert!(sizes[0] < sizes[1], "{sizes:?}");
+    Ok(())
+}
@@ -1,6 +1,6 @@
 #[cfg(test)]
 mod test {
-    use simple_parser::{Parse, Result};
+    use simple_parser::{Parse, Prelude, Result, SourceFile};
     use std::io::Cursor;
 
     #[test]
@@ -8,7 +8,7 @@ mod test {
         let input_str = include_str!("function.j");
         let mut parse = Parse::test_instance(Cursor::new(input_str))?;
         parse.file()?;
-        parse.show();
+        print!("{}", parse.program());
         Ok(())
     }
 
@@ -17,7 +17,7 @@ mod test {
         let input_str = include_str!("common.j");
         let mut parse = Parse::test_instance(Cursor::new(input_str))?;
         parse.file()?;
-        parse.show();
+        print!("{}", parse.program());
         Ok(())
     }
 
@@ -31,7 +31,48 @@ mod test {
             parse.show_pos();
             return Err(x);
         }
-        parse.show();
+        print!("{}", parse.program());
+        Ok(())
+    }
+
+    #[test]
+    fn test_blizzardj_newlines() -> Result<()> {
+        let commonj = include_str!("common.j");
+        let blizzardj = include_str!("blizzard.j");
+        let input_str = format!("{commonj}\n{blizzardj}");
+        let mut parse = Parse::test_instance(Cursor::new(input_str.as_str()))?.with_newlines(true);
+        parse.file()?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_blizzardj_slice() -> Result<()> {
+        let commonj = include_str!("common.j");
+        let blizzardj = include_str!("blizzard.j");
+        let input_str = format!("{commonj}\n{blizzardj}");
+        let mut parse = Parse::from_slice(input_str.as_bytes());
+        parse.file()?;
+        let warnings = parse.codegen().warnings();
+        assert!(warnings.is_empty(), "{warnings:?}");
+        Ok(())
+    }
+
+    #[test]
+    fn test_blizzardj_prelude() -> Result<()> {
+        let prelude = Prelude::compile([
+            SourceFile::new("common.j", include_bytes!("common.j").to_vec()),
+            SourceFile::new("blizzard.j", include_bytes!("blizzard.j").to_vec()),
+        ])?;
+        for map in [
+            "call BJDebugMsg(\"a\")",
+            "call DisplayTextToPlayer(Player(0), 0, 0, \"b\")",
+        ] {
+            let text = format!("function main takes nothing returns nothing\n{map}\nendfunction\n");
+            let mut unit = prelude.unit();
+            unit.add_source(SourceFile::new("war3map.j", text.into_bytes()));
+            unit.compile()?;
+            assert!(unit.program().strings().len() > prelude.codegen().program().strings().len());
+        }
         Ok(())
     }
 }
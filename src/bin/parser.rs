@@ -1,10 +1,10 @@
-use std::io::Cursor;
+use std::io::IsTerminal;
 use std::path::Path;
 use std::{env, fs};
 
 use simple_parser::*;
 
-fn main() -> Result<()> {
+fn main() -> std::result::Result<(), Box<dyn std::error::Error>> {
     let mut args = env::args();
 
     let path = args.next().unwrap();
@@ -15,32 +15,57 @@ fn main() -> Result<()> {
         .to_string();
 
     let mut files = vec![];
+    let mut encoding = SourceEncoding::Utf8;
+    let mut debug = false;
     loop {
         let next = args.next();
         let Some(next) = next else {
             break;
         };
+        if next == "--encoding" {
+            let Some(name) = args.next() else {
+                return Err("--encoding expect utf8 | gbk | big5 | cp1252 | auto".into());
+            };
+            encoding = name.parse()?;
+            continue;
+        }
+        if next == "--debug" {
+            debug = true;
+            continue;
+        }
         files.push(next);
     }
 
-    if files.len() == 0 {
-        println!("examples: parser file1.j file2.j ...");
+    if files.is_empty() {
+        println!(
+            "examples: parser [--debug] [--encoding utf8|gbk|big5|cp1252|auto] file1.j file2.j ..."
+        );
         return Ok(());
     }
-    let mut concat: Vec<u8> = vec![];
+    // 按命令行顺序编译，后面的文件可以使用前面文件的声明
+    let mut unit = CompilationUnit::new().with_debug(debug).with_recovery(true);
     for file in files {
-        let mut cur = fs::read(file).unwrap();
-        concat.append(&mut cur);
-        concat.push(b'\n');
+        let text = fs::read(&file)?;
+        unit.add_source(SourceFile::new(file, text).with_encoding(encoding));
     }
 
     // exec
-    let mut parse = Parse::test_instance(Cursor::new(concat))?;
-    if let Err(x) = parse.file() {
-        parse.show_pos();
-        return Err(x);
+    let result = unit.compile();
+    let emitter = Emitter::new(unit.sources()).with_color(std::io::stderr().is_terminal());
+    if let Err(x) = result {
+        let mut errors = unit.errors().to_vec();
+        if errors.is_empty() {
+            errors.push(x);
+        }
+        for e in errors {
+            eprint!("{}", emitter.render(&e));
+        }
+        return Err("compile failed".into());
     }
-    parse.show();
+    for warning in unit.codegen().warnings() {
+        eprint!("{}", emitter.render(warning));
+    }
+    print!("{}", unit.program());
 
     Ok(())
 }